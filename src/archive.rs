@@ -0,0 +1,182 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use users::{os::unix::UserExt, User};
+
+use crate::{
+    config::Ctx,
+    report::{self, Kind},
+    Cli,
+};
+
+/// stands for the repo in an exported config
+const REPO: &str = "{repo}";
+/// stands for the dir that modules with a custom path are imported into
+const MODULES: &str = "{modules}";
+
+/// packs the repo, the modules with custom paths and the config into a tar archive.
+/// paths in the config are rewritten so that they work in any home
+pub fn export(ctx: &Ctx, out: &str) -> Result<()> {
+    let out = std::env::current_dir()?.join(out);
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&out)
+        .with_context(|| format!("could not create {:?}", &out))?;
+    if let Err(err) = pack(ctx, file) {
+        let _ = fs::remove_file(&out);
+        return Err(err);
+    }
+
+    report::action(Kind::Created, "exported", &[("archive", &out)]);
+    Ok(())
+}
+
+fn pack(ctx: &Ctx, file: File) -> Result<()> {
+    let mut builder = tar::Builder::new(file);
+    // symlinks in the repo are kept as symlinks
+    builder.follow_symlinks(false);
+
+    let mut doc = fs::read_to_string(&ctx.config_file)?.parse::<toml_edit::Document>()?;
+    doc["repo"] = toml_edit::value(REPO);
+    let mut modules = Vec::new();
+    if let Some(descs) = doc
+        .get_mut("modules")
+        .and_then(|m| m.as_array_of_tables_mut())
+    {
+        for m in descs.iter_mut() {
+            let (Some(name), Some(path)) = (
+                m.get("name").and_then(|n| n.as_str()).map(String::from),
+                m.get("path").and_then(|p| p.as_str()),
+            ) else {
+                continue;
+            };
+            let path = shellexpand::tilde_with_context(path, || {
+                Some(ctx.canon_home_dir.to_string_lossy())
+            })
+            .into_owned();
+            modules.push((PathBuf::from(path).join(&name), name));
+            m["path"] = toml_edit::value(MODULES);
+        }
+    }
+    let mut conf = doc.to_string();
+    for home in [&ctx.home_dir, &ctx.canon_home_dir] {
+        conf = conf.replace(&format!("\"{}/", home.to_string_lossy()), "\"~/");
+    }
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(conf.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, "config.toml", conf.as_bytes())?;
+    builder
+        .append_dir_all("repo", &ctx.canon_repo)
+        .with_context(|| format!("could not pack the repo {:?}", &ctx.canon_repo))?;
+    for (dir, name) in modules {
+        builder
+            .append_dir_all(Path::new("modules").join(&name), &dir)
+            .with_context(|| format!("could not pack module {} at {:?}", &name, &dir))?;
+    }
+    builder.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// unpacks an exported archive. the repo goes to dest and the config is rewritten to
+/// point at it
+pub fn import(cli: &Cli, non_root_user: &User, archive: &str, dest: Option<&str>) -> Result<()> {
+    let home_dir = non_root_user.home_dir();
+    let config_dir = Ctx::config_dir(cli, home_dir)?;
+    let config_file = Ctx::config_file(cli, home_dir, &config_dir);
+    if config_file.exists() {
+        return Err(anyhow!("config already exists at {:?}", &config_file));
+    }
+
+    let archive = std::env::current_dir()?.join(archive);
+    let dest = dest
+        .map(|r| shellexpand::tilde_with_context(r, || Some(home_dir.to_string_lossy())))
+        .map(|r| PathBuf::from(r.into_owned()))
+        .unwrap_or_else(|| home_dir.join(".local/share/configma"));
+    if dest.exists() && dest.read_dir()?.next().is_some() {
+        return Err(anyhow!("path {:?} already exists and is not empty", &dest));
+    }
+
+    let conf = unpack(&archive, &dest, &config_dir)?;
+    fs::write(&config_file, conf)?;
+    report::action(
+        Kind::Created,
+        "imported",
+        &[("repo", &dest), ("config", &config_file)],
+    );
+    report::skipped("use switch-profile to apply a profile");
+    Ok(())
+}
+
+/// the repo and the modules are unpacked into temp dirs next to where they go, so that
+/// nothing is left half unpacked when the archive is bad, and so that they can be renamed
+/// into place
+fn unpack(archive: &Path, dest: &Path, config_dir: &Path) -> Result<String> {
+    let dest_parent = dest.parent().context("can not import into /")?;
+    let modules = config_dir.join("modules");
+    fs::create_dir_all(dest_parent)?;
+    fs::create_dir_all(&modules)?;
+    let repo_staging = tempfile::Builder::new()
+        .prefix(".configma-import-")
+        .tempdir_in(dest_parent)?;
+    let modules_staging = tempfile::Builder::new()
+        .prefix(".configma-import-")
+        .tempdir_in(config_dir)?;
+
+    let file = File::open(archive).with_context(|| format!("could not open {:?}", archive))?;
+    let mut tar = tar::Archive::new(file);
+    tar.set_preserve_permissions(true);
+    let mut conf = None;
+    for e in tar.entries()? {
+        let mut e = e?;
+        let path = e.path()?.into_owned();
+        let unpacked = if path == Path::new("config.toml") {
+            conf = Some(io::read_to_string(&mut e)?);
+            true
+        } else if path.starts_with("repo") {
+            e.unpack_in(repo_staging.path())?
+        } else if path.starts_with("modules") {
+            e.unpack_in(modules_staging.path())?
+        } else {
+            false
+        };
+        if !unpacked {
+            return Err(anyhow!("unexpected path in the archive: {:?}", &path));
+        }
+    }
+    let conf = conf.context("not an archive made by configma export")?;
+
+    let staged_modules = modules_staging.path().join("modules");
+    if staged_modules.exists() {
+        for m in fs::read_dir(&staged_modules)? {
+            let to = modules.join(m?.file_name());
+            if to.exists() {
+                return Err(anyhow!("path already exists: {:?}", &to));
+            }
+        }
+    }
+    if dest.exists() {
+        // empty (checked earlier)
+        fs::remove_dir(dest)?;
+    }
+    fs::rename(repo_staging.path().join("repo"), dest)?;
+    if staged_modules.exists() {
+        for m in fs::read_dir(&staged_modules)? {
+            let m = m?;
+            fs::rename(m.path(), modules.join(m.file_name()))?;
+        }
+    }
+
+    let quoted = |p: &Path| toml_edit::Value::from(p.to_string_lossy().as_ref()).to_string();
+    let conf = conf
+        .replace(&format!("\"{}\"", REPO), &quoted(dest))
+        .replace(&format!("\"{}\"", MODULES), &quoted(&modules));
+    Ok(conf)
+}
@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Ctx, DirPolicy},
+    module::Module,
+    oplog::Op,
+    profile::{Profile, SymlinkSrc},
+};
+
+/// an operation read by batch. paths are resolved like the ones given to add and remove
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum BatchOp {
+    Add {
+        src: Vec<String>,
+        /// defaults to default_module
+        module: Option<String>,
+        #[serde(default)]
+        copy_only: bool,
+    },
+    Remove {
+        src: Vec<String>,
+        /// the active module with the highest precedence that tracks the path if not given
+        module: Option<String>,
+        #[serde(default)]
+        dump_only: bool,
+    },
+}
+
+/// what happened to an operation. paths are where the entries are in the repo (also the ones
+/// that were done before an operation failed)
+#[derive(Serialize, Debug)]
+struct Outcome {
+    index: usize,
+    op: &'static str,
+    ok: bool,
+    paths: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl BatchOp {
+    fn name(&self) -> &'static str {
+        match self {
+            BatchOp::Add { .. } => "add",
+            BatchOp::Remove { .. } => "remove",
+        }
+    }
+
+    /// pushes the paths that are done to paths, so that they are known even if a later
+    /// one fails
+    fn apply(&self, profile: &mut Profile, ctx: &Ctx, paths: &mut Vec<PathBuf>) -> Result<()> {
+        match self {
+            BatchOp::Add {
+                src,
+                module,
+                copy_only,
+            } => {
+                let name = module
+                    .as_ref()
+                    .or(ctx.conf.default_module.as_ref())
+                    .context("no module given and no default_module in the config")?;
+                // dirs are always added file by file to such modules
+                let expand = profile
+                    .modules
+                    .get(name)
+                    .map(|m| m.dir_policy == DirPolicy::Individual)
+                    .unwrap_or(false);
+                for src in src.iter() {
+                    let srcs = match expand {
+                        true => Profile::expand_dir(src, ctx, &[], &[])?
+                            .into_iter()
+                            .map(|p| p.to_string_lossy().into_owned())
+                            .collect(),
+                        false => vec![src.clone()],
+                    };
+                    for src in srcs.iter() {
+                        let dest =
+                            profile.add(src, ctx, name, *copy_only, SymlinkSrc::Refuse, None)?;
+                        if dest.is_some() {
+                            let path = Module::resolve_path(src, ctx)?;
+                            Op::add(name, &path, ctx).record(ctx)?;
+                        }
+                        paths.extend(dest);
+                    }
+                }
+            }
+            BatchOp::Remove {
+                src,
+                module,
+                dump_only,
+            } => {
+                for src in src.iter() {
+                    for target in profile.expand_tracked(src, ctx, module.as_deref())? {
+                        let target = target.to_string_lossy();
+                        let dest = match module {
+                            Some(name) => profile.remove(&target, ctx, name, *dump_only)?,
+                            None => profile.remove_from_active(&target, ctx, false, *dump_only)?,
+                        };
+                        if let Some(m) = profile
+                            .modules
+                            .values()
+                            .find(|m| dest.starts_with(&m.module_dir))
+                        {
+                            if let Ok(e) = m.entry_from_dest(&dest, ctx) {
+                                Op::remove(&m.name, &e.src, ctx).record(ctx)?;
+                            }
+                        }
+                        paths.push(dest);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// runs a json list of operations in order against a single loaded profile (instead of
+/// loading every module once per invocation), and prints what happened to each of them.
+/// stops at the first failure unless keep_going
+pub fn run(profile: &mut Profile, input: &str, keep_going: bool, ctx: &Ctx) -> Result<()> {
+    let ops = serde_json::from_str::<Vec<BatchOp>>(input).context("bad batch of operations")?;
+
+    let mut outcomes = Vec::new();
+    let mut failed = 0;
+    for (index, op) in ops.iter().enumerate() {
+        let mut paths = Vec::new();
+        let res = op.apply(profile, ctx, &mut paths);
+        if res.is_err() {
+            failed += 1;
+        }
+        outcomes.push(Outcome {
+            index,
+            op: op.name(),
+            ok: res.is_ok(),
+            paths,
+            error: res.err().map(|err| format!("{:?}", err)),
+        });
+        if failed > 0 && !keep_going {
+            break;
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&outcomes)?);
+
+    if failed > 0 {
+        let not_run = match ops.len() - outcomes.len() {
+            0 => String::new(),
+            n => format!(" ({} not run)", n),
+        };
+        return Err(anyhow!(
+            "{} of {} operations failed{}",
+            failed,
+            ops.len(),
+            not_run
+        ));
+    }
+    Ok(())
+}
@@ -1,28 +1,406 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsStr,
+    fs,
+    io::{self, Read, Write},
+    os::unix::{
+        fs::{MetadataExt, OpenOptionsExt, PermissionsExt},
+        process::CommandExt,
+    },
+    panic,
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, OnceLock},
+    thread, time,
+};
 
-use anyhow::{anyhow, Result};
-use nix::unistd;
+use anyhow::{anyhow, Context, Result};
+use nix::{errno::Errno, unistd};
 use serde::{Deserialize, Serialize};
 use users::{os::unix::UserExt, User};
 
-use crate::{entry::Privilege, Cli};
+use crate::{
+    confirm,
+    dump_archive::{self, DumpArchive},
+    entry::{LinkMode, Privilege},
+    error::Error,
+    module::PathKind,
+    profile::IfExists,
+    report::{self, Kind, Output},
+    Cli, Command, EditTarget,
+};
 
-#[derive(Deserialize, Debug)]
+pub const CONFIG_FILE: &str = "config.toml";
+pub const PROFILE_FILE: &str = "profile.active.toml";
+/// largest file add --from-url downloads
+pub const MAX_DOWNLOAD: usize = 16 * 1024 * 1024;
+/// dir in the repo that the dirs of profiles are in. hidden, so it is never read as a module
+pub const PROFILES_DIR: &str = ".profiles";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub repo: String,
     pub default_module: Option<String>,
     pub profiles: Vec<ProfileDesc>,
     pub modules: Vec<ModuleDesc>,
+
+    #[serde(default)]
+    pub dump_layout: DumpLayout,
+
+    #[serde(default)]
+    pub dump_format: DumpFormat,
+
+    /// error if the active profile does not contain the default module
+    #[serde(default = "default_true")]
+    pub require_default_module: bool,
+
+    /// file that every change made with root privileges is logged to
+    pub audit_log: Option<String>,
+
+    #[serde(default)]
+    pub special_files: SpecialFiles,
+
+    /// dirs inside home that resolve to somewhere outside of it (symlinks, bind mounts).
+    /// paths under them are still treated as home paths
+    #[serde(default)]
+    pub home_roots: Vec<String>,
+
+    /// dump entries with uncommitted changes before removing them from a module
+    #[serde(default)]
+    pub safe_remove: bool,
+
+    #[serde(default)]
+    pub retry: Retry,
+
+    #[serde(default)]
+    pub defaults: Defaults,
+
+    /// paths that can not be added (on top of DENIED_PATHS)
+    #[serde(default)]
+    pub denied_paths: Vec<String>,
+
+    /// send a desktop notification when a sync finishes
+    #[serde(default)]
+    pub notify: bool,
+
+    /// warn about entries that are not committed to the git repo (on sync and status)
+    #[serde(default)]
+    pub check_git_tracked: bool,
+
+    /// keep the dirs in modules that are left empty when an entry is removed
+    #[serde(default)]
+    pub keep_empty_dirs: bool,
+
+    /// make the files in the repo owned and readable by the user on every sync and add
+    #[serde(default)]
+    pub fix_permissions: bool,
+
+    /// file that every add, remove and switch-profile is recorded in (for replay)
+    pub operations_log: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            repo: String::new(),
+            default_module: None,
+            profiles: Vec::new(),
+            modules: Vec::new(),
+            dump_layout: Default::default(),
+            dump_format: Default::default(),
+            require_default_module: true,
+            audit_log: None,
+            special_files: Default::default(),
+            home_roots: Vec::new(),
+            safe_remove: false,
+            retry: Default::default(),
+            defaults: Default::default(),
+            denied_paths: Vec::new(),
+            notify: false,
+            check_git_tracked: false,
+            keep_empty_dirs: false,
+            fix_permissions: false,
+            operations_log: None,
+        }
+    }
+}
+
+/// system paths that are never managed. managing anything under them or any dir
+/// containing them is refused too (except for anything under '/')
+pub const DENIED_PATHS: &[&str] = &[
+    "/",
+    "/boot",
+    "/dev",
+    "/proc",
+    "/run",
+    "/sys",
+    "/etc/passwd",
+    "/etc/shadow",
+    "/etc/sudoers",
+];
+
+/// flags that are turned on for a command unless --no-defaults is passed
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    #[serde(default)]
+    pub sync: SyncDefaults,
+    #[serde(default)]
+    pub switch_profile: SwitchDefaults,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct SyncDefaults {
+    #[serde(default)]
+    pub force: bool,
+    pub if_exists: Option<IfExists>,
+    #[serde(default)]
+    pub restore_dumped: bool,
+    #[serde(default)]
+    pub keep_going: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct SwitchDefaults {
+    #[serde(default)]
+    pub force: bool,
+    pub if_exists: Option<IfExists>,
+    #[serde(default)]
+    pub restore_dumped: bool,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+impl Defaults {
+    /// turns on the flags set in the config. flags passed on the command line stay on
+    pub fn apply(&self, command: &mut Command) {
+        match command {
+            Command::Sync {
+                force,
+                if_exists,
+                restore_dumped,
+                keep_going,
+                link_only: false,
+                ..
+            } => {
+                // --if-exists (or -f) on the command line wins
+                if !*force && if_exists.is_none() {
+                    *if_exists = self.sync.if_exists;
+                }
+                *force |= self.sync.force && if_exists.is_none();
+                *restore_dumped |= self.sync.restore_dumped;
+                *keep_going |= self.sync.keep_going;
+            }
+            Command::SwitchProfile {
+                force,
+                if_exists,
+                restore_dumped,
+                confirm,
+                ..
+            } => {
+                if !*force && if_exists.is_none() {
+                    *if_exists = self.switch_profile.if_exists;
+                }
+                *force |= self.switch_profile.force && if_exists.is_none();
+                *restore_dumped |= self.switch_profile.restore_dumped;
+                *confirm |= self.switch_profile.confirm;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// retrying of filesystem operations that fail with errors that go away on their own
+/// (network mounts)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Retry {
+    /// 1 means no retries
+    pub attempts: u32,
+    /// wait before the first retry. doubles after every retry
+    pub backoff_ms: u64,
+}
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff_ms: 100,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// how dumped paths are laid out inside a dump dir
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DumpLayout {
+    /// same as in the module dirs (home/.. or etc/..)
+    #[default]
+    Mirror,
+    /// a single file/dir per dumped path with the full path encoded in it's name
+    Flat,
+    /// mirrored, but inside a directory named after the module the entry belongs to
+    ByModule,
+}
+
+/// how the dumps of a run are stored
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// a dir with the dumped paths in it
+    #[default]
+    #[serde(rename = "tree")]
+    Tree,
+    /// a zstd compressed tar archive with the dumped paths in it. saves space when big dirs
+    /// are dumped. restored paths are not removed from the archive
+    #[serde(rename = "tar.zst")]
+    TarZst,
 }
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ProfileDesc {
     pub name: String,
     pub modules: Vec<String>,
+
+    /// paths that are removed (and dumped) on every sync
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub absent: Vec<String>,
+}
+impl ProfileDesc {
+    /// parses a profile passed on the command line as json or toml
+    pub fn parse_inline(s: &str) -> Result<Self> {
+        if s.trim_start().starts_with('{') {
+            serde_json::from_str(s).context("invalid json profile")
+        } else {
+            toml::from_str(s).context("invalid toml profile")
+        }
+    }
+}
+
+/// module and profile names are used as dir names, so they must not be able to escape the
+/// dir they are joined to. names starting with a '.' are reserved (for STUBs, .git etc)
+pub fn validate_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        return Err(anyhow!("{} name must not be empty", kind));
+    }
+    if name.contains(['/', '\\', '\0']) {
+        return Err(anyhow!(
+            "{} name '{}' must not contain path separators",
+            kind,
+            name
+        ));
+    }
+    if name.starts_with('.') {
+        return Err(anyhow!(
+            "{} name '{}' must not start with a '.'",
+            kind,
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// what to do with sockets, fifos and devices inside dirs that are copied
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecialFiles {
+    #[default]
+    Error,
+    /// leave them out of the copy (with a warning)
+    Skip,
+}
+
+/// how a module links the dirs that are added to it
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirPolicy {
+    /// the dir is a single entry (with a STUB) and is linked as a whole
+    #[default]
+    Whole,
+    /// every file in the dir is a separate entry. the dirs are real dirs
+    Individual,
 }
-#[derive(Deserialize, Debug)]
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct ModuleDesc {
     pub name: String,
     pub path: Option<String>,
+
+    /// url of a git repo that is the module. it is cloned into the config dir and can not be
+    /// added to or removed from
+    pub git: Option<String>,
+    /// commit, branch or tag of the git repo to use
+    pub rev: Option<String>,
+
+    #[serde(default)]
+    pub mode: LinkMode,
+
+    #[serde(default)]
+    pub dir_policy: DirPolicy,
+
+    /// the module is only linked if this command is on PATH
+    pub requires: Option<String>,
+    /// the module is only linked if this path exists
+    pub requires_path: Option<String>,
+
+    /// home dir the module was made in. entries under it are linked into the current home
+    pub home_root: Option<String>,
+
+    /// user whose home the home entries are linked into (needs root)
+    pub user: Option<String>,
+
+    /// extra paths that an entry is linked to, keyed by the path of the entry
+    #[serde(default)]
+    pub aliases: BTreeMap<String, Vec<String>>,
+}
+impl ModuleDesc {
+    /// why the module should not be linked on this machine
+    pub fn unmet_requirement(&self, ctx: &Ctx) -> Option<String> {
+        if let Some(cmd) = &self.requires {
+            let found = std::env::var_os("PATH")
+                .map(|paths| {
+                    std::env::split_paths(&paths).any(|dir| {
+                        dir.join(cmd)
+                            .metadata()
+                            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                            .unwrap_or(false)
+                    })
+                })
+                .unwrap_or(false);
+            if !found {
+                return Some(format!("command '{}' not found", cmd));
+            }
+        }
+        if let Some(path) = &self.requires_path {
+            let expanded = shellexpand::tilde_with_context(path, || {
+                Some(ctx.canon_home_dir.to_string_lossy())
+            });
+            if !Path::new(expanded.as_ref()).exists() {
+                return Some(format!("path '{}' does not exist", path));
+            }
+        }
+        None
+    }
+}
+
+/// the locations a Ctx is built from
+#[derive(Debug, Clone)]
+pub struct CtxPaths {
+    pub home_dir: PathBuf,
+    pub config_dir: PathBuf,
+    pub config_file: PathBuf,
+    /// name of the dump dir of this run (see Ctx::dump_dir)
+    pub dump_base: PathBuf,
+    pub repo: PathBuf,
 }
 
 #[derive(Debug)]
@@ -32,40 +410,54 @@ pub struct Ctx {
 
     pub home_dir: PathBuf,
     pub canon_home_dir: PathBuf,
+    /// (canonical path, path relative to home) of every home root
+    pub home_roots: Vec<(PathBuf, PathBuf)>,
+    pub audit_log: Option<PathBuf>,
 
     pub conf: Config,
-    pub _config_dir: PathBuf,
-    pub dump_dir: PathBuf,
+    pub config_dir: PathBuf,
+    pub config_file: PathBuf,
+    /// what the dump dir of this run is named, unless another run already took the name
+    pub dump_base: PathBuf,
+    /// created by the first dump of this run
+    dump_dir: OnceLock<PathBuf>,
+    /// created by the first dump of this run (with dump_format = "tar.zst")
+    dump_archive: OnceLock<DumpArchive>,
+    /// where the paths in the audit log were dumped to in this run
+    audited_dumps: Mutex<HashMap<PathBuf, PathBuf>>,
     pub profile_file: PathBuf,
 
     pub repo: PathBuf,
     pub canon_repo: PathBuf,
+
+    pub output: Output,
+    /// fetch modules from git remotes instead of only using the cached clones
+    pub fetch_remotes: bool,
+    /// skip the DENIED_PATHS check
+    pub allow_denied: bool,
+    /// how add tracks paths (--as-home / --as-root). guessed from the path if None
+    pub classify_as: Option<PathKind>,
+    /// do not fail on entries of deactivated modules that are not linked
+    pub force_unlink: bool,
+    /// do not remove the dirs in modules that a removal leaves empty
+    pub keep_dirs: bool,
+    /// move legacy profile dirs (see Ctx::migrate_profile_dirs)
+    pub migrate_profiles: bool,
 }
 
 impl Ctx {
     pub fn new(cli: &Cli, root_user: Option<User>, non_root_user: User) -> Result<Self> {
         let home_dir = non_root_user.home_dir();
-        let config_dir = {
-            let config_dir = home_dir.join(".config/configma");
-
-            if cli.config_dir.is_none() && !config_dir.exists() {
-                fs::create_dir(&config_dir)?;
-            }
-
-            cli.config_dir
-                .as_ref()
-                .map(|p| shellexpand::tilde_with_context(p, || Some(home_dir.to_string_lossy())))
-                .map(|s| s.to_string())
-                .map(PathBuf::from)
-                .map(|p| p.canonicalize())
-                .unwrap_or(Ok(config_dir))?
-        };
+        let config_dir = Self::config_dir(cli, home_dir)?;
+        let config_file = Self::config_file(cli, home_dir, &config_dir);
+        let config_file = Self::follow_redirect(&config_file, home_dir)?;
 
         let conf: Config = {
-            let config_file_path = config_dir.join("config.toml");
-            if config_file_path.exists() {
-                let contents = std::fs::read_to_string(config_file_path)?;
-                toml::from_str(&contents)?
+            if config_file.exists() {
+                let contents = std::fs::read_to_string(&config_file)?;
+                // unknown keys are mostly typos, so they are not ignored
+                toml::from_str(&contents)
+                    .with_context(|| format!("invalid config {:?}", &config_file))?
             } else {
                 return Err(anyhow!(
                     "Create a git repo and add the path to it in ~/.config/configma/config.toml."
@@ -80,35 +472,1194 @@ impl Ctx {
             PathBuf::from(r)
         };
 
-        let dump_dir = config_dir.join("dumps").join(format!(
-            "{}",
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)?
-                .as_millis()
+        // dumps are kept per profile. switching dumps into the profile being switched to
+        let profile = match &cli.command {
+            Command::SwitchProfile { name, .. } => Some(name.clone()),
+            _ => fs::read_to_string(config_dir.join(PROFILE_FILE))
+                .ok()
+                .and_then(|p| toml::from_str::<ProfileDesc>(&p).ok())
+                .map(|p| p.name),
+        };
+        let dumps = match &profile {
+            Some(name) => Self::dumps_of(&config_dir, name),
+            None => config_dir.join("dumps"),
+        };
+        // sorts in the order the dumps were created in
+        let dump_base = dumps.join(format!(
+            "{}-{}",
+            chrono::Utc::now().format("%Y%m%d-%H%M%S%.3f"),
+            cli.command.name(),
         ));
 
-        let home_dir = non_root_user.home_dir().to_path_buf();
+        let paths = CtxPaths {
+            home_dir: non_root_user.home_dir().to_path_buf(),
+            config_dir,
+            config_file,
+            dump_base,
+            repo,
+        };
+        let mut s = Self::with_paths(conf, paths, root_user, non_root_user)?;
+        s.output = cli.output;
+        s.fetch_remotes = matches!(
+            cli.command,
+            Command::Sync { .. } | Command::SwitchProfile { .. }
+        );
+        s.force_unlink = matches!(
+            cli.command,
+            Command::Sync {
+                force_unlink: true,
+                ..
+            } | Command::SwitchProfile {
+                force_unlink: true,
+                ..
+            } | Command::Plan {
+                force_unlink: true,
+                ..
+            }
+        );
+        s.migrate_profiles = matches!(
+            cli.command,
+            Command::Sync { .. }
+                | Command::SwitchProfile { .. }
+                | Command::NewProfile { .. }
+                | Command::Migrate { .. }
+        );
+        s.keep_dirs |= matches!(
+            cli.command,
+            Command::Remove {
+                keep_dirs: true,
+                ..
+            }
+        );
+        s.allow_denied = matches!(
+            cli.command,
+            Command::Add {
+                i_know_what_im_doing: true,
+                ..
+            }
+        );
+        s.classify_as = match cli.command {
+            Command::Add { as_home: true, .. } => Some(PathKind::Home),
+            Command::Add { as_root: true, .. } => Some(PathKind::Root),
+            _ => None,
+        };
+        Ok(s)
+    }
+
+    /// does not look at the environment or the user's home. everything
+    /// configma touches is derived from the given paths
+    pub fn with_paths(
+        conf: Config,
+        paths: CtxPaths,
+        root_user: Option<User>,
+        non_root_user: User,
+    ) -> Result<Self> {
+        let CtxPaths {
+            home_dir,
+            config_dir,
+            config_file,
+            dump_base,
+            repo,
+        } = paths;
+
+        for m in &conf.modules {
+            validate_name("module", &m.name)?;
+            if m.git.is_some() && m.path.is_some() {
+                return Err(anyhow!(
+                    "module '{}' can not have both a path and a git url",
+                    &m.name
+                ));
+            }
+        }
+        for p in &conf.profiles {
+            validate_name("profile", &p.name)?;
+        }
+
+        let profile_file = config_dir.join(PROFILE_FILE);
+        let canon_home_dir = home_dir.canonicalize()?;
+
+        let mut home_roots = Vec::new();
+        for root in &conf.home_roots {
+            let path = PathBuf::from(
+                shellexpand::tilde_with_context(root, || Some(home_dir.to_string_lossy()))
+                    .into_owned(),
+            );
+            let Some(relative) = path
+                .strip_prefix(&home_dir)
+                .or(path.strip_prefix(&canon_home_dir))
+                .ok()
+                .filter(|r| !r.as_os_str().is_empty())
+            else {
+                return Err(anyhow!("home root '{}' must be inside the home dir", root));
+            };
+            let canon = path
+                .canonicalize()
+                .with_context(|| format!("home root '{}' does not exist", root))?;
+            home_roots.push((canon, relative.to_path_buf()));
+        }
 
-        let profile_file = config_dir.join("profile.active.toml");
+        let audit_log = conf.audit_log.as_ref().map(|p| {
+            PathBuf::from(
+                shellexpand::tilde_with_context(p, || Some(home_dir.to_string_lossy()))
+                    .into_owned(),
+            )
+        });
 
         let s = Self {
-            canon_home_dir: home_dir.canonicalize()?,
+            canon_home_dir,
+            home_roots,
+            audit_log,
             home_dir,
-            conf,
-            _config_dir: config_dir,
-            dump_dir,
+            config_dir,
+            config_file,
+            dump_base,
+            dump_dir: OnceLock::new(),
+            dump_archive: OnceLock::new(),
+            audited_dumps: Mutex::new(HashMap::new()),
             profile_file,
             canon_repo: repo.canonicalize()?,
+            output: Output::default(),
+            fetch_remotes: false,
+            allow_denied: false,
+            classify_as: None,
+            force_unlink: false,
+            keep_dirs: conf.keep_empty_dirs,
+            migrate_profiles: false,
             repo,
             root_user,
             non_root_user,
+            conf,
         };
         Ok(s)
     }
 
-    pub fn escalate_privileges(&self) -> Result<Privilege<'_>> {
+    /// the dir that this run dumps to. it is created on the first call, so runs that dump
+    /// nothing leave nothing behind. a counter is appended to the name if another run
+    /// (started in the same millisecond) already has a dir with it
+    pub fn dump_dir(&self) -> Result<&Path> {
+        if let Some(dir) = self.dump_dir.get() {
+            return Ok(dir);
+        }
+
+        let parent = self.dump_base.parent().expect("dumps are in a dir");
+        fs::create_dir_all(parent)?;
+        let mut dir = self.dump_base.clone();
+        for n in 1.. {
+            match fs::create_dir(&dir) {
+                Ok(()) => break,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    let name = self.dump_base.file_name().expect("has a name");
+                    dir = parent.join(format!("{}-{}", name.to_string_lossy(), n));
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("could not create dump dir {:?}", &dir))
+                }
+            }
+        }
+        Ok(self.dump_dir.get_or_init(|| dir))
+    }
+
+    /// the archive that this run dumps to (see Ctx::dump_dir)
+    pub fn dump_archive(&self) -> Result<&DumpArchive> {
+        if let Some(archive) = self.dump_archive.get() {
+            return Ok(archive);
+        }
+
+        let parent = self.dump_base.parent().expect("dumps are in a dir");
+        fs::create_dir_all(parent)?;
+        let name = self.dump_base.file_name().expect("has a name");
+        let mut path = parent.join(format!("{}.{}", name.to_string_lossy(), dump_archive::EXT));
+        for n in 1.. {
+            match fs::File::create_new(&path) {
+                Ok(file) => {
+                    let archive = DumpArchive::new(path, file)?;
+                    return Ok(self.dump_archive.get_or_init(|| archive));
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    path = parent.join(format!(
+                        "{}-{}.{}",
+                        name.to_string_lossy(),
+                        n,
+                        dump_archive::EXT
+                    ));
+                }
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("could not create dump archive {:?}", &path))
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    /// where this run dumps to (a dir, or an archive with dump_format = "tar.zst")
+    pub fn dump_target(&self) -> Result<&Path> {
+        match self.conf.dump_format {
+            DumpFormat::Tree => self.dump_dir(),
+            DumpFormat::TarZst => Ok(self.dump_archive()?.path()),
+        }
+    }
+
+    /// the dir that the dumps of a profile are kept in
+    pub fn dumps_of(config_dir: &Path, profile: &str) -> PathBuf {
+        config_dir.join("dumps").join(profile)
+    }
+
+    /// the dir that state (dumps, active profile, ..) is kept in. defaults to the dir
+    /// of --config if only that is given
+    pub fn config_dir(cli: &Cli, home_dir: &Path) -> Result<PathBuf> {
+        let expand = |p: &String| {
+            PathBuf::from(
+                shellexpand::tilde_with_context(p, || Some(home_dir.to_string_lossy()))
+                    .into_owned(),
+            )
+        };
+
+        if let Some(dir) = &cli.config_dir {
+            return Ok(expand(dir).canonicalize()?);
+        }
+        if let Some(file) = &cli.config {
+            // the file itself may not exist yet (bootstrap)
+            let dir = match expand(file).parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            return dir
+                .canonicalize()
+                .with_context(|| format!("dir of config file '{}' does not exist", file));
+        }
+
+        let config_dir = home_dir.join(".config/configma");
+        if !config_dir.exists() {
+            fs::create_dir_all(&config_dir)?;
+        }
+        Ok(config_dir)
+    }
+
+    pub fn config_file(cli: &Cli, home_dir: &Path, config_dir: &Path) -> PathBuf {
+        match &cli.config {
+            Some(file) => PathBuf::from(
+                shellexpand::tilde_with_context(file, || Some(home_dir.to_string_lossy()))
+                    .into_owned(),
+            ),
+            None => config_dir.join(CONFIG_FILE),
+        }
+    }
+
+    /// a config with only `redirect = "path"` in it forwards to the config at that path
+    /// (relative to the dir of the config), e.g. one that is versioned in the repo. returns
+    /// the config file to load. redirects are followed only once
+    pub fn follow_redirect(config_file: &Path, home_dir: &Path) -> Result<PathBuf> {
+        let Some(target) = Self::redirect_of(config_file)? else {
+            return Ok(config_file.to_path_buf());
+        };
+        let target = PathBuf::from(
+            shellexpand::tilde_with_context(&target, || Some(home_dir.to_string_lossy()))
+                .into_owned(),
+        );
+        let target = config_file
+            .parent()
+            .map(|p| p.join(&target))
+            .unwrap_or(target);
+        let canon = target.canonicalize().with_context(|| {
+            format!(
+                "config {:?} redirects to {:?}, which does not exist",
+                config_file, &target
+            )
+        })?;
+        if canon == config_file.canonicalize()? {
+            return Err(anyhow!("config {:?} redirects to itself", config_file));
+        }
+        if Self::redirect_of(&canon)?.is_some() {
+            return Err(anyhow!(
+                "config {:?} redirects to {:?}, which redirects again. point it at the real config",
+                config_file,
+                &canon
+            ));
+        }
+        Ok(canon)
+    }
+
+    /// the redirect in the config file (if it is a redirecting one)
+    fn redirect_of(config_file: &Path) -> Result<Option<String>> {
+        if !config_file.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(config_file)?;
+        let Ok(table) = toml::from_str::<toml::Table>(&contents) else {
+            // the error is reported when the config itself is parsed
+            return Ok(None);
+        };
+        let Some(redirect) = table.get("redirect") else {
+            return Ok(None);
+        };
+        if table.len() > 1 {
+            return Err(anyhow!(
+                "config {:?} has a redirect, so it can not have any other keys",
+                config_file
+            ));
+        }
+        match redirect.as_str() {
+            Some(r) => Ok(Some(r.to_owned())),
+            None => Err(anyhow!(
+                "redirect in config {:?} must be a path",
+                config_file
+            )),
+        }
+    }
+
+    /// clones the repo (if not already cloned) and writes a config.toml pointing at it.
+    /// returns the name of the profile that should be switched to.
+    pub fn bootstrap(
+        cli: &Cli,
+        non_root_user: &User,
+        url: &str,
+        repo: Option<&str>,
+        profile: Option<&str>,
+    ) -> Result<String> {
+        let home_dir = non_root_user.home_dir();
+        let config_dir = Self::config_dir(cli, home_dir)?;
+        let config_file_path = Self::config_file(cli, home_dir, &config_dir);
+
+        let repo = repo
+            .map(|r| shellexpand::tilde_with_context(r, || Some(home_dir.to_string_lossy())))
+            .map(|r| PathBuf::from(r.into_owned()))
+            .unwrap_or_else(|| home_dir.join(".local/share/configma"));
+
+        if repo.join(".git").exists() {
+            report::skipped(format!(
+                "repo already cloned at {:?}. skipping clone",
+                &repo
+            ));
+        } else if repo.exists() && repo.read_dir()?.next().is_some() {
+            return Err(anyhow!(
+                "path {:?} already exists and is not a git repo",
+                &repo
+            ));
+        } else {
+            report::action(
+                Kind::Created,
+                "cloning repo",
+                &[("url", Path::new(url)), ("dst", &repo)],
+            );
+            fs::create_dir_all(repo.parent().expect("must have a parent"))?;
+            let status = as_user(
+                process::Command::new("git")
+                    .arg("clone")
+                    .arg(url)
+                    .arg(&repo),
+                non_root_user,
+            )
+            .status()
+            .context("could not run git. is it installed?")?;
+            if !status.success() {
+                return Err(anyhow!("git clone failed with {}", status));
+            }
+        }
+        let repo = repo.canonicalize()?;
+
+        let conf = if config_file_path.exists() {
+            let contents = fs::read_to_string(&config_file_path)?;
+            let conf: Config = toml::from_str(&contents)
+                .with_context(|| format!("invalid config {:?}", &config_file_path))?;
+            let configured = PathBuf::from(
+                shellexpand::tilde_with_context(&conf.repo, || Some(home_dir.to_string_lossy()))
+                    .into_owned(),
+            );
+            if configured.canonicalize().ok().as_ref() != Some(&repo) {
+                return Err(anyhow!(
+                    "config at {:?} already points to a different repo: {:?}",
+                    &config_file_path,
+                    &conf.repo
+                ));
+            }
+            conf
+        } else {
+            // the repo may carry a config.toml at it's root to use as a template
+            let template = repo.join(CONFIG_FILE);
+            let mut conf = if template.is_file() {
+                toml::from_str(&fs::read_to_string(&template)?)?
+            } else {
+                Config::default()
+            };
+            conf.repo = repo.to_string_lossy().into_owned();
+            fs::write(&config_file_path, toml::to_string_pretty(&conf)?)?;
+            report::action(
+                Kind::Created,
+                "created config",
+                &[("path", &config_file_path)],
+            );
+            conf
+        };
+
+        match profile {
+            Some(name) => {
+                if !conf.profiles.iter().any(|p| p.name == name) {
+                    return Err(anyhow!(
+                        "profile with name: '{}' not found in {:?}",
+                        name,
+                        &config_file_path
+                    ));
+                }
+                Ok(name.to_owned())
+            }
+            None => conf.profiles.first().map(|p| p.name.clone()).ok_or(anyhow!(
+                "no profiles in {:?}. add one and re-run bootstrap",
+                &config_file_path
+            )),
+        }
+    }
+
+    /// opens the file in $EDITOR and validates it before replacing the original.
+    /// returns true if a sync should follow.
+    pub fn edit(cli: &Cli, non_root_user: &User, what: EditTarget) -> Result<bool> {
+        let config_dir = Self::config_dir(cli, non_root_user.home_dir())?;
+        let file = match what {
+            EditTarget::Config => Self::follow_redirect(
+                &Self::config_file(cli, non_root_user.home_dir(), &config_dir),
+                non_root_user.home_dir(),
+            )?,
+            EditTarget::Profile => config_dir.join(PROFILE_FILE),
+        };
+        if !file.exists() {
+            return Err(anyhow!("file does not exist: {:?}", &file));
+        }
+
+        let original = fs::read_to_string(&file)?;
+        let tmp = file.with_extension("edit.toml");
+        fs::write(&tmp, &original)?;
+
+        let editor = std::env::var("EDITOR").unwrap_or("vi".to_owned());
+        let mut editor = editor.split_whitespace();
+        let program = editor.next().context("EDITOR is empty")?;
+        let args = editor.collect::<Vec<_>>();
+
+        let edited = loop {
+            let status = as_user(
+                process::Command::new(program).args(&args).arg(&tmp),
+                non_root_user,
+            )
+            .status()
+            .with_context(|| format!("could not run editor '{}'", program))?;
+            if !status.success() {
+                let _ = fs::remove_file(&tmp);
+                return Err(anyhow!("editor exited with {}", status));
+            }
+
+            let contents = fs::read_to_string(&tmp)?;
+            let res = match what {
+                EditTarget::Config => toml::from_str::<Config>(&contents).map(|_| ()),
+                EditTarget::Profile => toml::from_str::<ProfileDesc>(&contents).map(|_| ()),
+            };
+            match res {
+                Ok(()) => break contents,
+                Err(err) => {
+                    report::warning(format!("invalid config: {}", err));
+                    if !confirm("edit again?")? {
+                        fs::remove_file(&tmp)?;
+                        report::skipped("changes discarded");
+                        return Ok(false);
+                    }
+                }
+            }
+        };
+        fs::rename(&tmp, &file)?;
+
+        match what {
+            EditTarget::Config => Ok(false),
+            EditTarget::Profile => {
+                let before = toml::from_str::<ProfileDesc>(&original)?;
+                let after = toml::from_str::<ProfileDesc>(&edited)?;
+                if before.modules == after.modules {
+                    return Ok(false);
+                }
+                confirm("module list changed. sync now?")
+            }
+        }
+    }
+
+    /// points config.repo at the new location of the repo (keeping the rest of the file).
+    /// returns where the repo was before
+    pub fn set_repo(cli: &Cli, non_root_user: &User, new_repo: &str) -> Result<PathBuf> {
+        let home_dir = non_root_user.home_dir();
+        let config_dir = Self::config_dir(cli, home_dir)?;
+        let file = Self::config_file(cli, home_dir, &config_dir);
+        let expand = |p: &str| {
+            PathBuf::from(
+                shellexpand::tilde_with_context(p, || Some(home_dir.to_string_lossy()))
+                    .into_owned(),
+            )
+        };
+
+        let mut doc = fs::read_to_string(&file)
+            .with_context(|| format!("could not read config file {:?}", &file))?
+            .parse::<toml_edit::Document>()?;
+        let old = doc["repo"]
+            .as_str()
+            .map(expand)
+            .context("repo is not set in the config")?;
+
+        let new = expand(new_repo)
+            .canonicalize()
+            .with_context(|| format!("repo does not exist at '{}'", new_repo))?;
+        if !new.is_dir() {
+            return Err(anyhow!("repo must be a dir: {:?}", &new));
+        }
+        if !new.join(".git").exists() {
+            report::warning(format!("{:?} is not a git repo", &new));
+        }
+
+        let new_str = match new.strip_prefix(home_dir) {
+            Ok(p) => Path::new("~").join(p),
+            Err(_) => new.clone(),
+        };
+        doc["repo"] = toml_edit::value(new_str.to_string_lossy().into_owned());
+        fs::write(&file, doc.to_string())?;
+        report::action(Kind::Created, "moved repo", &[("from", &old), ("to", &new)]);
+        Ok(old)
+    }
+
+    pub fn profile_names(&self) -> String {
+        if self.conf.profiles.is_empty() {
+            return "(none)".to_owned();
+        }
+        self.conf
+            .profiles
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// writes the module list of a profile back into config.toml, keeping the rest of the
+    /// file (comments, formatting) as is
+    pub fn save_profile_modules(&self, name: &str, modules: &[String]) -> Result<()> {
+        let path = &self.config_file;
+        let mut doc = fs::read_to_string(path)?.parse::<toml_edit::Document>()?;
+        let profile = doc["profiles"]
+            .as_array_of_tables_mut()
+            .and_then(|profiles| {
+                profiles
+                    .iter_mut()
+                    .find(|p| p.get("name").and_then(|n| n.as_str()) == Some(name))
+            })
+            .with_context(|| format!("profile '{}' not found in {:?}", name, &path))?;
+
+        let mut list = toml_edit::Array::new();
+        for m in modules {
+            list.push(m.as_str());
+        }
+        profile["modules"] = toml_edit::value(list);
+
+        fs::write(path, doc.to_string())?;
+        Ok(())
+    }
+
+    /// makes sure the clone of a git module exists (and is at the required rev if fetching).
+    /// returns the dir the module dir is in
+    pub fn remote_module(&self, desc: &ModuleDesc) -> Result<PathBuf> {
+        let url = desc.git.as_ref().expect("must be a git module");
+        let parent = self.config_dir.join("remote");
+        let dir = parent.join(&desc.name);
+        let git = |args: &[&std::ffi::OsStr]| -> Result<bool> {
+            let status = as_user(process::Command::new("git").args(args), &self.non_root_user)
+                .stdout(process::Stdio::null())
+                .status()
+                .context("could not run git. is it installed?")?;
+            Ok(status.success())
+        };
+
+        let cloned = dir.join(".git").exists();
+        if !cloned {
+            report::action(
+                Kind::Created,
+                format!("cloning module '{}'", &desc.name),
+                &[("url", Path::new(url)), ("dst", &dir)],
+            );
+            fs::create_dir_all(&parent)?;
+            if !git(&[
+                "clone".as_ref(),
+                "--quiet".as_ref(),
+                "--depth=1".as_ref(),
+                url.as_ref(),
+                dir.as_ref(),
+            ])? {
+                return Err(anyhow!(
+                    "could not clone module '{}' from {}",
+                    &desc.name,
+                    url
+                ));
+            }
+        }
+        if cloned && !self.fetch_remotes {
+            return Ok(parent);
+        }
+
+        let rev = desc.rev.as_deref().unwrap_or("HEAD");
+        let fetched = git(&[
+            "-C".as_ref(),
+            dir.as_ref(),
+            "fetch".as_ref(),
+            "--quiet".as_ref(),
+            "--depth=1".as_ref(),
+            // the url in the config might have changed since the clone
+            url.as_ref(),
+            rev.as_ref(),
+        ])? && git(&[
+            "-C".as_ref(),
+            dir.as_ref(),
+            "checkout".as_ref(),
+            "--quiet".as_ref(),
+            "--detach".as_ref(),
+            "FETCH_HEAD".as_ref(),
+        ])?;
+        match (fetched, cloned) {
+            (true, _) => (),
+            (false, true) => report::warning(format!(
+                "could not fetch module '{}' from {}. using the cached clone",
+                &desc.name, url
+            )),
+            (false, false) => {
+                return Err(anyhow!(
+                    "could not fetch rev '{}' of module '{}' from {}",
+                    rev,
+                    &desc.name,
+                    url
+                ))
+            }
+        }
+        Ok(parent)
+    }
+
+    /// downloads the file at the url (http or https only, redirects are followed)
+    pub fn download(&self, url: &str) -> Result<Vec<u8>> {
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            return Err(anyhow!(
+                "only http and https urls can be downloaded: {}",
+                url
+            ));
+        }
+
+        let res = ureq::get(url)
+            .call()
+            .with_context(|| format!("could not download {}", url))?;
+        // Content-Length can be missing or wrong, so the size is limited while reading
+        let mut contents = Vec::new();
+        res.into_body()
+            .into_reader()
+            .take(MAX_DOWNLOAD as u64 + 1)
+            .read_to_end(&mut contents)
+            .with_context(|| format!("could not download {}", url))?;
+        if contents.len() > MAX_DOWNLOAD {
+            return Err(anyhow!(
+                "{} is larger than {} bytes. download it and add it instead",
+                url,
+                MAX_DOWNLOAD
+            ));
+        }
+        Ok(contents)
+    }
+
+    /// new-profile used to create the dir of a profile at the top of the repo, where it is
+    /// read as a module. the empty ones that no profile uses as a module are the dirs of
+    /// profiles. the others are modules that happen to be named like a profile
+    fn legacy_profile_dirs(&self) -> Result<Vec<(&str, PathBuf)>> {
+        let mut dirs = Vec::new();
+        for p in &self.conf.profiles {
+            let old = self.canon_repo.join(&p.name);
+            if !old.is_dir() || old.is_symlink() {
+                continue;
+            }
+            let used = self
+                .conf
+                .profiles
+                .iter()
+                .any(|q| q.modules.contains(&p.name));
+            if used || old.read_dir()?.next().is_some() {
+                continue;
+            }
+            dirs.push((p.name.as_str(), old));
+        }
+        Ok(dirs)
+    }
+
+    /// moves the legacy profile dirs into PROFILES_DIR. commands that only look at the
+    /// repo just warn about them
+    pub fn migrate_profile_dirs(&self) -> Result<()> {
+        let dirs = self.legacy_profile_dirs()?;
+        if !self.migrate_profiles {
+            for (_, old) in dirs.iter() {
+                report::warning(format!(
+                    "profile dir {:?} is at the top of the repo. run sync or migrate to move it into {}",
+                    old, PROFILES_DIR
+                ));
+            }
+            return Ok(());
+        }
+
+        for (name, old) in dirs.iter() {
+            let new = self.canon_repo.join(PROFILES_DIR).join(name);
+            fs::create_dir_all(self.canon_repo.join(PROFILES_DIR))?;
+            if new.exists() {
+                fs::remove_dir(old)?;
+            } else {
+                fs::rename(old, &new)?;
+            }
+            report::action(
+                Kind::Created,
+                "moved profile dir",
+                &[("from", old), ("to", &new)],
+            );
+        }
+        Ok(())
+    }
+
+    /// true if the repo is a git repo with uncommitted changes
+    pub fn repo_is_dirty(&self) -> Result<bool> {
+        self.git_dirty(&self.canon_repo, None)
+    }
+
+    /// true if path is inside a git repo and has uncommitted changes
+    pub fn has_uncommitted(&self, path: &Path) -> Result<bool> {
+        let dir = match path.is_dir() {
+            true => path,
+            false => path.parent().expect("must have a parent"),
+        };
+        self.git_dirty(dir, Some(path))
+    }
+
+    fn git_dirty(&self, dir: &Path, path: Option<&Path>) -> Result<bool> {
+        let mut cmd = process::Command::new("git");
+        cmd.arg("-C").arg(dir).args(["status", "--porcelain"]);
+        if let Some(path) = path {
+            cmd.arg("--").arg(path);
+        }
+        let out = as_user(&mut cmd, &self.non_root_user)
+            .output()
+            .context("could not run git. is it installed?")?;
+
+        // not a git repo
+        if !out.status.success() {
+            return Ok(false);
+        }
+        Ok(!out.stdout.is_empty())
+    }
+
+    /// the commit a local branch of the repo points to
+    pub fn branch_head(&self, branch: &str) -> Result<String> {
+        self.repo_git(
+            None,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("refs/heads/{}^{{commit}}", branch),
+            ],
+        )
+        .with_context(|| format!("branch '{}' does not exist in the repo", branch))
+    }
+
+    /// commits the current state of the paths (in the repo) on top of branch. the working
+    /// tree, the index and the checked out branch are left alone, so uncommitted work
+    /// can not be lost
+    pub fn commit_to_branch(&self, branch: &str, paths: &[PathBuf], message: &str) -> Result<()> {
+        let old = self.branch_head(branch)?;
+        // in the git dir of the repo, where only the user can write
+        let index = self.repo_git(
+            None,
+            &[
+                "rev-parse",
+                "--git-path",
+                &format!("configma-index-{}", process::id()),
+            ],
+        )?;
+        let index = self.canon_repo.join(index);
+        let res = self.commit_with_index(&index, &old, paths, message);
+        let _ = fs::remove_file(&index);
+        let res = res.with_context(|| {
+            format!(
+                "the repo was changed, but nothing was committed to '{}'",
+                branch
+            )
+        });
+        let Some(new) = res? else {
+            report::skipped(format!("nothing to commit on branch '{}'", branch));
+            return Ok(());
+        };
+
+        let r = format!("refs/heads/{}", branch);
+        self.repo_git(None, &["update-ref", &r, &new, &old])
+            .with_context(|| format!("could not update branch '{}'", branch))?;
+
+        // the index of a checked out branch would show the commit reverted
+        if self
+            .repo_git(None, &["symbolic-ref", "--quiet", "HEAD"])
+            .ok()
+            == Some(r)
+        {
+            let mut args = vec!["reset".as_ref(), "--quiet".as_ref(), "--".as_ref()];
+            args.extend(paths.iter().map(|p| p.as_os_str()));
+            self.repo_git(None, &args)?;
+        }
+        report::action(
+            Kind::Created,
+            "committed",
+            &[
+                ("branch", &PathBuf::from(branch)),
+                ("commit", &PathBuf::from(new)),
+            ],
+        );
+        Ok(())
+    }
+
+    fn commit_with_index(
+        &self,
+        index: &Path,
+        parent: &str,
+        paths: &[PathBuf],
+        message: &str,
+    ) -> Result<Option<String>> {
+        let index = Some(index);
+        self.repo_git(index, &["read-tree", parent])?;
+        for p in paths {
+            let mut args: Vec<&OsStr> = match p.exists() || p.is_symlink() {
+                true => vec!["add".as_ref(), "--force".as_ref(), "--all".as_ref()],
+                false => vec![
+                    "rm".as_ref(),
+                    "-r".as_ref(),
+                    "--quiet".as_ref(),
+                    "--cached".as_ref(),
+                    "--ignore-unmatch".as_ref(),
+                ],
+            };
+            args.extend(["--".as_ref(), p.as_os_str()]);
+            self.repo_git(index, &args)?;
+        }
+        let tree = self.repo_git(index, &["write-tree"])?;
+        if self.repo_git(None, &["rev-parse", &format!("{}^{{tree}}", parent)])? == tree {
+            return Ok(None);
+        }
+        let commit = self.repo_git(index, &["commit-tree", &tree, "-p", parent, "-m", message])?;
+        Ok(Some(commit))
+    }
+
+    /// paths in the repo (absolute) that changed between rev and HEAD. deleted ones too
+    pub fn changed_since(&self, rev: &str) -> Result<Vec<PathBuf>> {
+        let files = self.repo_git(
+            None,
+            &["diff", "--name-only", "-z", "--relative", rev, "HEAD", "--"],
+        )?;
+        Ok(files
+            .split('\0')
+            .filter(|f| !f.is_empty())
+            .map(|f| self.canon_repo.join(f))
+            .collect())
+    }
+
+    /// every path in the repo that git tracks (relative to the repo). none if the repo is
+    /// not a git repo
+    pub fn git_tracked(&self) -> Result<Option<BTreeSet<PathBuf>>> {
+        let Ok(files) = self.repo_git(None, &["ls-files", "-z"]) else {
+            return Ok(None);
+        };
+        Ok(Some(
+            files
+                .split('\0')
+                .filter(|f| !f.is_empty())
+                .map(PathBuf::from)
+                .collect(),
+        ))
+    }
+
+    /// the paths (in the repo) that git ignores. None if the repo is not a git repo
+    pub fn git_ignored(&self, paths: &[PathBuf]) -> Result<Option<Vec<PathBuf>>> {
+        let paths = paths
+            .iter()
+            .filter(|p| p.starts_with(&self.canon_repo))
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        let mut cmd = process::Command::new("git");
+        cmd.arg("-C")
+            .arg(&self.canon_repo)
+            .args(["check-ignore", "--"])
+            .args(&paths);
+        let out = as_user(&mut cmd, &self.non_root_user)
+            .output()
+            .context("could not run git. is it installed?")?;
+        // 1 means that none of them are ignored
+        match out.status.code() {
+            Some(0) => Ok(Some(
+                String::from_utf8_lossy(&out.stdout)
+                    .lines()
+                    .map(|l| self.canon_repo.join(l))
+                    .collect(),
+            )),
+            Some(1) => Ok(Some(Vec::new())),
+            _ => Ok(None),
+        }
+    }
+
+    /// runs git in the repo and returns its trimmed stdout. index replaces the
+    /// index file of the repo
+    fn repo_git<S: AsRef<OsStr>>(&self, index: Option<&Path>, args: &[S]) -> Result<String> {
+        let mut cmd = process::Command::new("git");
+        cmd.arg("-C").arg(&self.canon_repo).args(args);
+        if let Some(index) = index {
+            cmd.env("GIT_INDEX_FILE", index);
+        }
+        let out = as_user(&mut cmd, &self.non_root_user)
+            .output()
+            .context("could not run git. is it installed?")?;
+        if !out.status.success() {
+            let cmd = args
+                .first()
+                .map(|a| a.as_ref().to_string_lossy())
+                .unwrap_or_default();
+            return Err(match String::from_utf8_lossy(&out.stderr).trim() {
+                "" => anyhow!("git {} failed", cmd),
+                err => anyhow!("git {} failed: {}", cmd, err),
+            });
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+    }
+
+    /// errors if the path is too dangerous to manage
+    pub fn check_denied(&self, path: &Path) -> Result<()> {
+        if self.allow_denied {
+            return Ok(());
+        }
+        let denied =
+            DENIED_PATHS
+                .iter()
+                .map(PathBuf::from)
+                .chain(self.conf.denied_paths.iter().map(|p| {
+                    PathBuf::from(
+                        shellexpand::tilde_with_context(p, || {
+                            Some(self.canon_home_dir.to_string_lossy())
+                        })
+                        .into_owned(),
+                    )
+                }));
+        for d in denied {
+            let under = d != Path::new("/") && path.starts_with(&d);
+            if path == d || under || d.starts_with(path) {
+                return Err(anyhow!(
+                    "refusing to manage {:?} because of the denied path {:?}. \
+                     use --i-know-what-im-doing if you really want this",
+                    path,
+                    &d
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// fs::remove_dir_all, but refuses to delete /, the home dir, the repo, the config dir,
+    /// dirs containing them and mount points. with in_repo, path must also be strictly
+    /// inside the repo, the config dir or the dir of a module with a custom path
+    pub fn remove_dir_all(&self, path: &Path, in_repo: bool) -> Result<()> {
+        let refuse = |why: String| Err(anyhow!("refusing to delete {:?}: {}", path, why));
+        if !path.is_absolute() {
+            return refuse("it is not an absolute path".to_owned());
+        }
+        if path
+            .components()
+            .any(|c| c == std::path::Component::ParentDir)
+        {
+            return refuse("it contains '..'".to_owned());
+        }
+        let protected = [
+            Path::new("/"),
+            &self.home_dir,
+            &self.canon_home_dir,
+            &self.repo,
+            &self.canon_repo,
+            &self.config_dir,
+        ];
+        if let Some(p) = protected.iter().find(|p| p.starts_with(path)) {
+            return refuse(format!("it is (or contains) {:?}", p));
+        }
+        if in_repo {
+            let roots = self.module_roots();
+            if !roots.iter().any(|r| path.starts_with(r) && path != r) {
+                return refuse("it is not inside the repo".to_owned());
+            }
+        }
+
+        let meta = fs::symlink_metadata(path)?;
+        if meta.file_type().is_symlink() {
+            return Ok(fs::remove_file(path)?);
+        }
+        let parent = path.parent().expect("not /").metadata()?;
+        if parent.dev() != meta.dev() {
+            return refuse("it is a mount point".to_owned());
+        }
+        Ok(fs::remove_dir_all(path)?)
+    }
+
+    /// dirs that modules (and their dumps and clones) are in
+    fn module_roots(&self) -> Vec<PathBuf> {
+        let mut roots = vec![self.canon_repo.clone(), self.config_dir.clone()];
+        for m in &self.conf.modules {
+            let Some(p) = &m.path else {
+                continue;
+            };
+            let p =
+                shellexpand::tilde_with_context(p, || Some(self.canon_home_dir.to_string_lossy()));
+            if let Ok(p) = Path::new(p.as_ref()).canonicalize() {
+                roots.push(p);
+            }
+        }
+        roots
+    }
+
+    /// sends a desktop notification with the result (if enabled). does nothing without a
+    /// session bus or a notification daemon. the effective user is the non root user here,
+    /// so it is sent to their session
+    pub fn notify(&self, what: &str, res: &Result<()>) {
+        if !self.conf.notify {
+            return;
+        }
+
+        let (summary, body) = match res {
+            Ok(()) => {
+                let (created, dumped) = report::counts();
+                (
+                    format!("configma {} done", what),
+                    format!("{} linked or copied, {} dumped or removed", created, dumped),
+                )
+            }
+            Err(err) => (format!("configma {} failed", what), format!("{:#}", err)),
+        };
+        // the notification is optional, so not being able to send it is not an error
+        let sent = notify_rust::Notification::new()
+            .appname("configma")
+            .summary(&summary)
+            .body(&body)
+            .show();
+        if let Err(err) = sent {
+            report::verbose(1, format!("could not send a notification: {}", err));
+        }
+    }
+
+    /// appends an event to the audit log (if one is configured). the log is written as root.
+    /// old is what was at path before. where path was dumped to is remembered, so that the
+    /// event that replaces it (like a symlink) says what it replaced
+    pub fn audit(
+        &self,
+        action: &str,
+        path: &Path,
+        mut old: serde_json::Value,
+        target: Option<&Path>,
+    ) -> Result<()> {
+        let Some(log) = &self.audit_log else {
+            return Ok(());
+        };
+
+        let mut dumped = self
+            .audited_dumps
+            .lock()
+            .map_err(|_| anyhow!("audit log {:?} is poisoned", log))?;
+        match (action, target) {
+            ("dump", Some(to)) => {
+                dumped.insert(path.to_path_buf(), to.to_path_buf());
+            }
+            _ if old.is_null() => {
+                if let Some(to) = dumped.remove(path) {
+                    old = serde_json::json!({ "dumped_to": to });
+                }
+            }
+            _ => (),
+        }
+        drop(dumped);
+
+        let event = serde_json::json!({
+            "time": chrono::Utc::now().to_rfc3339(),
+            "user": self.non_root_user.name().to_string_lossy(),
+            "action": action,
+            "path": path,
+            "old": old,
+            "target": target,
+        });
+
+        self.with_privileges(|| {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .mode(0o600)
+                .open(log)
+                .with_context(|| format!("could not open audit log {:?}", log))?;
+            writeln!(file, "{}", event)?;
+            Ok(())
+        })
+    }
+
+    /// runs f again (as configured in retry) while it fails with a transient error
+    pub fn retry<T>(&self, mut f: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let transient = [
+            Errno::EAGAIN,
+            Errno::EINTR,
+            Errno::EBUSY,
+            Errno::ESTALE,
+            Errno::ETIMEDOUT,
+        ];
+        let mut backoff = time::Duration::from_millis(self.conf.retry.backoff_ms);
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(err)
+                    if attempt < self.conf.retry.attempts
+                        && err
+                            .raw_os_error()
+                            .map(|e| transient.contains(&Errno::from_i32(e)))
+                            .unwrap_or(false) =>
+                {
+                    report::verbose(
+                        2,
+                        format!(
+                            "{} (attempt {} of {}). retrying in {:?}",
+                            err, attempt, self.conf.retry.attempts, backoff
+                        ),
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Ok(res) if attempt > 1 => {
+                    report::verbose(1, format!("worked after {} attempts", attempt));
+                    return Ok(res);
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// runs f as root. privileges are dropped again when f returns, fails or panics. nested
+    /// calls keep the privileges of the outermost call
+    pub fn with_privileges<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        if unistd::geteuid().is_root() {
+            return f();
+        }
+
+        let privilege = self.escalate_privileges()?;
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(f));
+        drop(privilege);
+        res.unwrap_or_else(|p| panic::resume_unwind(p))
+    }
+
+    pub fn with_privileges_if<T>(&self, needed: bool, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        match needed {
+            true => self.with_privileges(f),
+            false => f(),
+        }
+    }
+
+    fn escalate_privileges(&self) -> Result<Privilege<'_>> {
         let Some(root) = &self.root_user else {
-            return Err(anyhow!("No root privileges"));
+            return Err(Error::PrivilegeRequired.into());
         };
 
         unistd::setegid(unistd::Gid::from_raw(root.primary_group_id()))?;
@@ -117,3 +1668,24 @@ impl Ctx {
         Ok(Privilege { ctx: self })
     }
 }
+
+/// makes sure the command runs as the non root user even if configma was run using sudo
+pub fn as_user<'a>(cmd: &'a mut process::Command, user: &User) -> &'a mut process::Command {
+    if !unistd::getuid().is_root() {
+        return cmd;
+    }
+
+    let uid = unistd::Uid::from_raw(user.uid());
+    let gid = unistd::Gid::from_raw(user.primary_group_id());
+    // SAFETY: only async-signal-safe syscalls are made between fork and exec
+    unsafe {
+        cmd.pre_exec(move || {
+            // regain root to be able to drop all ids (real, effective and saved)
+            unistd::seteuid(unistd::Uid::from_raw(0))?;
+            unistd::setgroups(&[gid])?;
+            unistd::setgid(gid)?;
+            unistd::setuid(uid)?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Context, Result};
+use zstd::stream::AutoFinishEncoder;
+
+/// extension of the dump archives (dump_format = "tar.zst")
+pub const EXT: &str = "tar.zst";
+
+/// the archive that a run dumps to. paths are appended as they are dumped, and the
+/// archive is finished when it is dropped (at the end of the run)
+pub struct DumpArchive {
+    path: PathBuf,
+    builder: Mutex<tar::Builder<AutoFinishEncoder<'static, File>>>,
+}
+
+impl fmt::Debug for DumpArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DumpArchive")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DumpArchive {
+    /// file is created by the caller, so that it can pick a name nobody else has
+    pub fn new(path: PathBuf, file: File) -> Result<Self> {
+        let encoder = zstd::Encoder::new(file, 0)?.auto_finish();
+        let mut builder = tar::Builder::new(encoder);
+        // symlinks are dumped as symlinks
+        builder.follow_symlinks(false);
+        Ok(Self {
+            path,
+            builder: Mutex::new(builder),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// streams path (and everything in it) into the archive as name. modes are kept
+    pub fn append(&self, path: &Path, name: &Path) -> Result<()> {
+        let mut builder = self
+            .builder
+            .lock()
+            .map_err(|_| anyhow!("dump archive {:?} is poisoned", &self.path))?;
+        let res = if path.is_dir() && !path.is_symlink() {
+            builder.append_dir_all(name, path)
+        } else {
+            builder.append_path_with_name(path, name)
+        };
+        res.with_context(|| format!("could not dump {:?} to {:?}", path, &self.path))
+    }
+}
+
+/// splits a path like <archive>.tar.zst/home/.zshrc into the archive and the path in it
+pub fn split(dump: &Path) -> Option<(&Path, &Path)> {
+    dump.ancestors()
+        .skip(1)
+        .find(|a| a.to_string_lossy().ends_with(EXT) && a.is_file())
+        .map(|a| (a, dump.strip_prefix(a).expect("is an ancestor")))
+}
+
+fn open(archive: &Path) -> Result<tar::Archive<zstd::Decoder<'static, std::io::BufReader<File>>>> {
+    let file = File::open(archive).with_context(|| format!("could not open {:?}", archive))?;
+    Ok(tar::Archive::new(zstd::Decoder::new(file)?))
+}
+
+/// if the archive has the dumped path
+pub fn contains(archive: &Path, relative: &Path) -> Result<bool> {
+    for e in open(archive)?.entries()? {
+        if e?.path()? == relative {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// unpacks the dumped path (and everything in it if it is a dir) to `to`. the archive is
+/// left as it is
+pub fn extract(archive: &Path, relative: &Path, to: &Path) -> Result<()> {
+    let mut archive_r = open(archive)?;
+    archive_r.set_preserve_permissions(true);
+    let mut found = false;
+    for e in archive_r.entries()? {
+        let mut e = e?;
+        let path = e.path()?.into_owned();
+        let Ok(rest) = path.strip_prefix(relative) else {
+            continue;
+        };
+        let target = match rest.as_os_str().is_empty() {
+            true => to.to_path_buf(),
+            false => to.join(rest),
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        e.unpack(&target)
+            .with_context(|| format!("could not restore {:?} from {:?}", &path, archive))?;
+        found = true;
+    }
+    if !found {
+        return Err(anyhow!(
+            "{:?} is not in dump archive {:?}",
+            relative,
+            archive
+        ));
+    }
+    Ok(())
+}
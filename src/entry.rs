@@ -1,17 +1,31 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fs,
-    os::unix::{self, prelude::MetadataExt},
+    os::unix::{
+        self,
+        prelude::{MetadataExt, PermissionsExt},
+    },
     path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, Context, Result};
-use nix::unistd;
-
-use crate::config::Ctx;
+use nix::{errno::Errno, unistd};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Ctx, DumpFormat, DumpLayout, SpecialFiles},
+    dump_archive,
+    report::{self, Kind},
+    stats,
+};
 
 pub const STUB: &str = ".configma.stub";
+/// names of the symlinks in the dir that are entries (added with --track-symlink), one
+/// per line. other symlinks in modules are ignored
+pub const LINKS: &str = ".configma.links";
 pub const HOME: &str = "home";
+/// dump dir for entries that do not belong to any module
+pub const NO_MODULE: &str = ".no-module";
 
 #[derive(Debug)]
 pub struct Privilege<'a> {
@@ -29,7 +43,7 @@ impl<'a> Drop for Privilege<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum RelativePath {
     Home(PathBuf),
     NonHome(PathBuf),
@@ -51,51 +65,207 @@ impl RelativePath {
     }
 }
 
+/// how the entries of a module are put in place
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// src is a symlink to dest
+    #[default]
+    Symlink,
+    /// src is a copy of dest
+    Copy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    /// src is a symlink to dest (or an identical copy of it)
+    Linked,
+    /// nothing exists at src
+    Missing,
+    /// src has the same contents as dest but is not linked yet
+    Unsynced,
+    /// some other file/dir/symlink exists at src
+    Conflict,
+}
+
+impl EntryState {
+    pub fn kind(&self) -> Kind {
+        match self {
+            EntryState::Linked => Kind::Created,
+            EntryState::Missing => Kind::Skipped,
+            EntryState::Unsynced => Kind::Warning,
+            EntryState::Conflict => Kind::Error,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryState::Linked => "linked",
+            EntryState::Missing => "missing",
+            EntryState::Unsynced => "unsynced",
+            EntryState::Conflict => "conflict",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Entry {
     pub src: PathBuf,
     pub relative: RelativePath,
     pub dest: PathBuf,
+    pub mode: LinkMode,
+    /// name of the module this entry belongs to
+    pub module: Option<String>,
 }
 
 impl Entry {
-    pub fn get_priv<'a>(&self, ctx: &'a Ctx) -> Result<Option<Privilege<'a>>> {
+    /// runs f as root if changing src needs root
+    pub fn with_priv<T>(&self, ctx: &Ctx, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        ctx.with_privileges_if(self.needs_priv()?, f)
+    }
+
+    pub fn state(&self) -> Result<EntryState> {
+        stats::count_state();
+        match (self.mode, self.src.exists(), self.src.is_symlink()) {
+            (_, false, false) => Ok(EntryState::Missing),
+            // a tracked symlink resolves past dest (or not at all)
+            (LinkMode::Symlink, _, true)
+                if self.dest.is_symlink()
+                    && fs::read_link(&self.src).ok().as_ref() == Some(&self.dest) =>
+            {
+                Ok(EntryState::Linked)
+            }
+            (LinkMode::Symlink, true, true) if canonicalize(&self.src)? == self.dest => {
+                Ok(EntryState::Linked)
+            }
+            (LinkMode::Copy, true, false) if same_contents(&self.src, &self.dest) => {
+                Ok(EntryState::Linked)
+            }
+            (LinkMode::Symlink, true, false) if same_contents(&self.src, &self.dest) => {
+                Ok(EntryState::Unsynced)
+            }
+            _ => Ok(EntryState::Conflict),
+        }
+    }
+
+    /// shows what a path was resolved to (with --trace-resolution)
+    pub fn trace(&self, how: &str) {
+        let relative = match &self.relative {
+            RelativePath::Home(p) => format!("home {:?}", p),
+            RelativePath::NonHome(p) => format!("non home {:?}", p),
+        };
+        report::trace(format!(
+            "  {}\n    src:      {:?}\n    dest:     {:?}\n    relative: {}",
+            how, &self.src, &self.dest, relative
+        ));
+    }
+
+    /// privileged changes are recorded in the audit log. old is what was at src before
+    /// the change (see Entry::before)
+    fn audit(
+        &self,
+        ctx: &Ctx,
+        action: &str,
+        old: serde_json::Value,
+        target: Option<&Path>,
+    ) -> Result<()> {
         if self.needs_priv()? {
-            Ok(Some(ctx.escalate_privileges()?))
+            ctx.audit(action, &self.src, old, target)?;
+        }
+        Ok(())
+    }
+
+    /// what is at src, for the audit log. only looked at if there is one
+    fn before(&self, ctx: &Ctx) -> serde_json::Value {
+        if ctx.audit_log.is_none() {
+            return serde_json::Value::Null;
+        }
+        let Ok(meta) = fs::symlink_metadata(&self.src) else {
+            return serde_json::Value::Null;
+        };
+        if meta.is_symlink() {
+            serde_json::json!({ "kind": "symlink", "target": fs::read_link(&self.src).ok() })
+        } else if meta.is_dir() {
+            serde_json::json!({ "kind": "dir" })
+        } else if meta.is_file() {
+            serde_json::json!({
+                "kind": "file",
+                "size": meta.len(),
+                "mode": format!("{:o}", meta.mode() & 0o7777),
+            })
         } else {
-            Ok(None)
+            serde_json::json!({ "kind": "special" })
         }
     }
 
     pub fn needs_priv(&self) -> Result<bool> {
+        let owner = self
+            .src
+            .ancestors()
+            .skip(1)
+            .find(|p| p.exists())
+            .map(nix::sys::stat::stat)
+            .transpose()?
+            .map(|s| unistd::Uid::from_raw(s.st_uid))
+            .context("some ancestor of the path must exist")?;
         match &self.relative {
-            RelativePath::Home(_) => Ok(false),
-            RelativePath::NonHome(_) => {
-                // let pri = ctx.escalate_privileges();
-                // if the parent of the file/dir is root - then escilate privileges
-                if self
-                    .src
-                    .ancestors()
-                    .skip(1)
-                    .find(|p| p.exists())
-                    .map(nix::sys::stat::stat)
-                    .transpose()?
-                    .map(|s| s.st_uid)
-                    .map(unistd::Uid::from_raw)
-                    .map(unistd::Uid::is_root)
-                    .context("some ancestor of the path must exist")?
-                {
-                    // Ok(Some(pri?))
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
+            // the home of some other user
+            RelativePath::Home(_) => Ok(owner != unistd::geteuid()),
+            // if the parent of the file/dir is root - then escilate privileges
+            RelativePath::NonHome(_) => Ok(owner.is_root()),
+        }
+    }
+
+    /// path of this entry relative to a dump dir
+    pub fn dump_relative(&self, layout: DumpLayout) -> PathBuf {
+        let relative = self.relative.clone().relative();
+        match layout {
+            DumpLayout::Mirror => relative,
+            DumpLayout::Flat => PathBuf::from(
+                relative
+                    .to_string_lossy()
+                    .replace('%', "%25")
+                    .replace('/', "%2F"),
+            ),
+            DumpLayout::ByModule => {
+                PathBuf::from(self.module.as_deref().unwrap_or(NO_MODULE)).join(relative)
             }
         }
     }
 
+    /// copies whatever is at dest to the dump dir. dest is left untouched
+    pub fn dump_dest(&self, ctx: &Ctx) -> Result<PathBuf> {
+        if ctx.conf.dump_format == DumpFormat::TarZst {
+            let relative = self.dump_relative(ctx.conf.dump_layout);
+            let archive = ctx.dump_archive()?;
+            archive.append(&self.dest, &relative)?;
+            return Ok(archive.path().join(relative));
+        }
+
+        let dump_to = ctx
+            .dump_dir()?
+            .join(self.dump_relative(ctx.conf.dump_layout));
+        fs::create_dir_all(dump_to.parent().unwrap())?;
+
+        if self.dest.is_dir() {
+            copy_dir(&self.dest, &dump_to, ctx)?;
+        } else {
+            ctx.retry(|| fs::copy(&self.dest, &dump_to))
+                .with_context(|| format!("could not copy {:?}", &self.dest))?;
+        }
+        Ok(dump_to)
+    }
+
+    /// moves whatever is at src to the dump dir
     pub fn dump(&self, ctx: &Ctx) -> Result<()> {
-        let dump_to = ctx.dump_dir.join(self.relative.clone().relative());
+        if ctx.conf.dump_format == DumpFormat::TarZst {
+            return self.dump_to_archive(ctx);
+        }
+        let old = self.before(ctx);
+
+        let dump_to = ctx
+            .dump_dir()?
+            .join(self.dump_relative(ctx.conf.dump_layout));
         fs::create_dir_all(dump_to.parent().unwrap())?;
 
         let src_meta = self.src.parent().expect("must have a parent").metadata()?;
@@ -106,16 +276,14 @@ impl Entry {
         if self.src.is_file() || self.src.is_symlink() {
             if self.src.is_symlink() {
                 let to = fs::read_link(&self.src)?;
-                unix::fs::symlink(to, &dump_to)?;
+                ctx.retry(|| unix::fs::symlink(&to, &dump_to))?;
 
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                fs::remove_file(&self.src)?;
-                drop(p);
+                ctx.with_privileges_if(needs_priv, || Ok(fs::remove_file(&self.src)?))?;
             } else if same_dev && !needs_priv {
-                fs::rename(&self.src, &dump_to)?;
+                ctx.retry(|| fs::rename(&self.src, &dump_to))?;
             } else {
                 // needs read perms on src
-                match fs::copy(&self.src, &dump_to) {
+                match ctx.retry(|| fs::copy(&self.src, &dump_to)) {
                     Ok(_) => (),
                     Err(err) => {
                         if dump_to.exists() {
@@ -125,22 +293,14 @@ impl Entry {
                     }
                 }
 
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                fs::remove_file(&self.src)?;
-                drop(p);
+                ctx.with_privileges_if(needs_priv, || Ok(fs::remove_file(&self.src)?))?;
             }
         } else if self.src.is_dir() {
             if same_dev && !needs_priv {
-                fs::rename(&self.src, &dump_to)?;
+                ctx.retry(|| fs::rename(&self.src, &dump_to))?;
             } else {
                 // needs read perms on src
-                match fs_extra::dir::copy(
-                    &self.src,
-                    &dump_to,
-                    &fs_extra::dir::CopyOptions::new()
-                        .copy_inside(false)
-                        .content_only(true),
-                ) {
+                match copy_dir(&self.src, &dump_to, ctx) {
                     Ok(_) => (),
                     Err(err) => {
                         if dump_to.exists() {
@@ -150,9 +310,7 @@ impl Entry {
                     }
                 }
 
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                fs::remove_dir_all(&self.src)?;
-                drop(p);
+                ctx.with_privileges_if(needs_priv, || ctx.remove_dir_all(&self.src, false))?;
             }
         } else {
             return Err(anyhow!(
@@ -161,14 +319,75 @@ impl Entry {
             ));
         }
 
-        let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-        unix::fs::symlink(&self.dest, &self.src)?;
-        drop(p);
+        self.audit(ctx, "dump", old, Some(&dump_to))?;
+        Ok(())
+    }
+
+    /// appends whatever is at src to the dump archive and deletes it
+    fn dump_to_archive(&self, ctx: &Ctx) -> Result<()> {
+        if !self.src.exists() && !self.src.is_symlink() {
+            return Err(anyhow!(
+                "cannot handle this type of file or whatever: {:?}",
+                &self.src
+            ));
+        }
+        let old = self.before(ctx);
+        let archive = ctx.dump_archive()?;
+        let relative = self.dump_relative(ctx.conf.dump_layout);
+        // needs read perms on src
+        archive.append(&self.src, &relative)?;
+        ctx.with_privileges_if(self.needs_priv()?, || {
+            if self.src.is_dir() && !self.src.is_symlink() {
+                ctx.remove_dir_all(&self.src, false)
+            } else {
+                Ok(fs::remove_file(&self.src)?)
+            }
+        })?;
+
+        self.audit(ctx, "dump", old, Some(&archive.path().join(relative)))?;
+        Ok(())
+    }
+
+    /// moves a dumped path back to src. paths in dump archives are unpacked (and stay in
+    /// the archive)
+    pub fn restore_dump(&self, dump: impl AsRef<Path>, ctx: &Ctx) -> Result<()> {
+        let dump = dump.as_ref();
+        let old = self.before(ctx);
+        if let Some((archive, relative)) = dump_archive::split(dump) {
+            ctx.with_privileges_if(self.needs_priv()?, || {
+                dump_archive::extract(archive, relative, &self.src)
+            })?;
+            self.audit(ctx, "restore", old, Some(dump))?;
+            return Ok(());
+        }
+
+        let src_meta = self.src.parent().expect("must have a parent").metadata()?;
+        let dump_meta = dump.parent().expect("must have a parent").metadata()?;
+        let same_dev = src_meta.dev() == dump_meta.dev();
+        let needs_priv = self.needs_priv()?;
 
+        ctx.with_privileges_if(needs_priv, || {
+            if same_dev {
+                ctx.retry(|| fs::rename(dump, &self.src))?;
+            } else if dump.is_symlink() {
+                ctx.retry(|| unix::fs::symlink(fs::read_link(dump)?, &self.src))?;
+                fs::remove_file(dump)?;
+            } else if dump.is_dir() {
+                copy_dir(dump, &self.src, ctx)?;
+                ctx.remove_dir_all(dump, true)?;
+            } else {
+                ctx.retry(|| fs::copy(dump, &self.src))?;
+                fs::remove_file(dump)?;
+            }
+            Ok(())
+        })?;
+
+        self.audit(ctx, "restore", old, Some(dump))?;
         Ok(())
     }
 
     pub fn add(&self, ctx: &Ctx) -> Result<()> {
+        let old = self.before(ctx);
         fs::create_dir_all(self.dest.parent().unwrap())?;
 
         let src_meta = self.src.parent().expect("must have a parent").metadata()?;
@@ -178,10 +397,10 @@ impl Entry {
 
         if self.src.is_file() {
             if same_dev && !needs_priv {
-                fs::rename(&self.src, &self.dest)?;
+                ctx.retry(|| fs::rename(&self.src, &self.dest))?;
             } else {
                 // needs read perms on src
-                match fs::copy(&self.src, &self.dest) {
+                match ctx.retry(|| fs::copy(&self.src, &self.dest)) {
                     Ok(_) => (),
                     Err(err) => {
                         if self.dest.exists() {
@@ -191,22 +410,14 @@ impl Entry {
                     }
                 }
 
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                fs::remove_file(&self.src)?;
-                drop(p);
+                ctx.with_privileges_if(needs_priv, || Ok(fs::remove_file(&self.src)?))?;
             }
         } else if self.src.is_dir() {
             if same_dev && !needs_priv {
-                fs::rename(&self.src, &self.dest)?;
+                ctx.retry(|| fs::rename(&self.src, &self.dest))?;
             } else {
                 // needs read perms on src
-                match fs_extra::dir::copy(
-                    &self.src,
-                    &self.dest,
-                    &fs_extra::dir::CopyOptions::new()
-                        .copy_inside(false)
-                        .content_only(true),
-                ) {
+                match copy_dir(&self.src, &self.dest, ctx) {
                     Ok(_) => (),
                     Err(err) => {
                         if self.dest.exists() {
@@ -216,9 +427,7 @@ impl Entry {
                     }
                 }
 
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                fs::remove_dir_all(&self.src)?;
-                drop(p);
+                ctx.with_privileges_if(needs_priv, || ctx.remove_dir_all(&self.src, false))?;
             }
             let _ = fs::File::create(self.dest.join(STUB))?;
         } else {
@@ -228,65 +437,124 @@ impl Entry {
             ));
         }
 
-        let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-        unix::fs::symlink(&self.dest, &self.src)?;
-        drop(p);
+        self.audit(ctx, "add", old, Some(&self.dest))?;
+        self.materialize(ctx)?;
+
+        Ok(())
+    }
+
+    /// src is a symlink that is moved into the repo as it is. a relative target is made
+    /// absolute, as the link does not stay in the same dir
+    pub fn add_symlink(&self, ctx: &Ctx) -> Result<()> {
+        let old = self.before(ctx);
+        let target = fs::read_link(&self.src)?;
+        let target = self.src.parent().expect("must have a parent").join(target);
+        fs::create_dir_all(self.dest.parent().unwrap())?;
+        unix::fs::symlink(&target, &self.dest)?;
+        set_tracked_link(&self.dest, true)?;
+        self.with_priv(ctx, || Ok(fs::remove_file(&self.src)?))?;
+        self.audit(ctx, "add", old, Some(&self.dest))?;
+        self.materialize(ctx)
+    }
+
+    /// src is a symlink. the contents of what it points to are copied into the repo and
+    /// the symlink (not it's target) is replaced with a link to dest
+    pub fn add_dereferenced(&self, ctx: &Ctx) -> Result<()> {
+        let old = self.before(ctx);
+        let target = canonicalize(&self.src)
+            .with_context(|| format!("could not follow the symlink at {:?}", &self.src))?;
+        let resolved = Entry {
+            src: target,
+            relative: self.relative.clone(),
+            dest: self.dest.clone(),
+            mode: self.mode,
+            module: self.module.clone(),
+        };
+        resolved.copy_to_dest(ctx)?;
+
+        self.with_priv(ctx, || Ok(fs::remove_file(&self.src)?))?;
+        self.audit(ctx, "add", old, Some(&self.dest))?;
+        self.materialize(ctx)?;
+        Ok(())
+    }
+
+    /// copies src into the repo without touching it
+    pub fn copy_to_dest(&self, ctx: &Ctx) -> Result<()> {
+        fs::create_dir_all(self.dest.parent().unwrap())?;
+
+        if self.src.is_file() {
+            // needs read perms on src
+            ctx.retry(|| fs::copy(&self.src, &self.dest))?;
+        } else if self.src.is_dir() {
+            match copy_dir(&self.src, &self.dest, ctx) {
+                Ok(_) => (),
+                Err(err) => {
+                    if self.dest.exists() {
+                        let _ = fs::remove_dir_all(&self.dest);
+                    }
+                    return Err(err)?;
+                }
+            }
+            let _ = fs::File::create(self.dest.join(STUB))?;
+        } else {
+            return Err(anyhow!(
+                "cannot handle this type of file or whatever: {:?}",
+                &self.src
+            ));
+        }
 
         Ok(())
     }
 
     pub fn remove(&self, ctx: &Ctx) -> Result<()> {
+        let old = self.before(ctx);
         let src_meta = self.src.parent().expect("must have a parent").metadata()?;
         let dest_meta = self.dest.parent().expect("must have a parent").metadata()?;
         let same_dev = src_meta.dev() == dest_meta.dev();
         let needs_priv = self.needs_priv()?;
 
-        let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-        fs::remove_file(&self.src)?;
-        drop(p);
-        if self.dest.is_dir() {
+        if self.mode == LinkMode::Copy && self.state()? != EntryState::Linked {
+            return Err(anyhow!(
+                "copy at {:?} differs from the repo. sync or remove it manually first",
+                &self.src
+            ));
+        }
+
+        self.rm_src_file(ctx)?;
+        if self.dest.is_symlink() {
+            // a tracked symlink is put back as it is
+            let target = fs::read_link(&self.dest)?;
+            self.with_priv(ctx, || Ok(unix::fs::symlink(&target, &self.src)?))?;
+            fs::remove_file(&self.dest)?;
+        } else if self.dest.is_dir() {
             fs::remove_file(self.dest.join(STUB))?;
             if same_dev && !needs_priv {
-                fs::rename(&self.dest, &self.src)?;
+                ctx.retry(|| fs::rename(&self.dest, &self.src))?;
             } else {
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                match fs_extra::dir::copy(
-                    &self.dest,
-                    &self.src,
-                    &fs_extra::dir::CopyOptions::new()
-                        .copy_inside(false)
-                        .content_only(true),
-                ) {
-                    Ok(_) => (),
-                    Err(err) => {
+                ctx.with_privileges_if(needs_priv, || {
+                    copy_dir(&self.dest, &self.src, ctx).inspect_err(|_| {
                         if self.src.exists() {
                             let _ = fs::remove_dir_all(&self.src);
                         }
                         let _ = unix::fs::symlink(&self.dest, &self.src);
-                        drop(p);
-                        return Err(err)?;
-                    }
-                }
-                drop(p);
-                fs::remove_dir_all(&self.dest)?;
+                    })
+                })?;
+                ctx.remove_dir_all(&self.dest, true)?;
             }
         } else if self.dest.is_file() {
             if same_dev && !needs_priv {
-                fs::rename(&self.dest, &self.src)?;
+                ctx.retry(|| fs::rename(&self.dest, &self.src))?;
             } else {
-                let p = needs_priv.then(|| ctx.escalate_privileges()).transpose()?;
-                match fs::copy(&self.dest, &self.src) {
-                    Ok(_) => (),
-                    Err(err) => {
-                        if self.src.exists() {
-                            let _ = fs::remove_file(&self.src);
-                        }
-                        let _ = unix::fs::symlink(&self.dest, &self.src);
-                        drop(p);
-                        return Err(err)?;
-                    }
-                }
-                drop(p);
+                ctx.with_privileges_if(needs_priv, || {
+                    ctx.retry(|| fs::copy(&self.dest, &self.src))
+                        .inspect_err(|_| {
+                            if self.src.exists() {
+                                let _ = fs::remove_file(&self.src);
+                            }
+                            let _ = unix::fs::symlink(&self.dest, &self.src);
+                        })?;
+                    Ok(())
+                })?;
                 fs::remove_file(&self.dest)?;
             }
         } else {
@@ -295,66 +563,406 @@ impl Entry {
                 &self.src
             ));
         }
+
+        self.audit(ctx, "remove", old, Some(&self.dest))?;
         Ok(())
     }
 
     pub fn rm_src_file(&self, ctx: &Ctx) -> Result<()> {
-        let p = self.get_priv(ctx)?;
+        let old = self.before(ctx);
+        self.with_priv(ctx, || {
+            if self.src.is_dir() && !self.src.is_symlink() {
+                ctx.remove_dir_all(&self.src, false)?;
+            } else {
+                fs::remove_file(&self.src)?;
+            }
+            Ok(())
+        })?;
+        self.audit(ctx, "delete", old, None)?;
+        Ok(())
+    }
 
-        fs::remove_file(&self.src)?;
+    /// puts dest in place at src according to the link mode
+    pub fn materialize(&self, ctx: &Ctx) -> Result<()> {
+        match self.mode {
+            LinkMode::Symlink => self.symlink_to_src(ctx),
+            LinkMode::Copy => self.copy_to_src(ctx),
+        }
+    }
 
-        drop(p);
+    pub fn copy_to_src(&self, ctx: &Ctx) -> Result<()> {
+        let old = self.before(ctx);
+        self.with_priv(ctx, || {
+            if self.dest.is_dir() {
+                copy_dir(&self.dest, &self.src, ctx)?;
+                let _ = fs::remove_file(self.src.join(STUB));
+            } else {
+                ctx.retry(|| fs::copy(&self.dest, &self.src))?;
+            }
+            Ok(())
+        })?;
+        self.audit(ctx, "copy", old, Some(&self.dest))?;
         Ok(())
     }
 
     pub fn symlink_to_src(&self, ctx: &Ctx) -> Result<()> {
-        let p = self.get_priv(ctx)?;
-
-        unix::fs::symlink(&self.dest, &self.src)?;
-
-        drop(p);
+        let old = self.before(ctx);
+        let res = self.with_priv(ctx, || {
+            Ok(ctx.retry(|| unix::fs::symlink(&self.dest, &self.src))?)
+        });
+        // something else could have created src since it was checked
+        if let Err(err) = res {
+            let exists = err
+                .downcast_ref::<std::io::Error>()
+                .map(|e| e.kind() == std::io::ErrorKind::AlreadyExists)
+                .unwrap_or(false);
+            if !exists {
+                return Err(err);
+            }
+            if fs::read_link(&self.src).ok().as_ref() == Some(&self.dest) {
+                return Ok(());
+            }
+            return Err(anyhow!(
+                "{:?} was created by something else during the sync",
+                &self.src
+            ));
+        }
+        self.audit(ctx, "symlink", old, Some(&self.dest))?;
         Ok(())
     }
 }
 
+/// copies the contents of the dir from into to. sockets, fifos and devices can not be
+/// copied, so they are skipped or an error depending on Config::special_files
+pub fn copy_dir(from: &Path, to: &Path, ctx: &Ctx) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for e in fs::read_dir(from).with_context(|| format!("could not read dir {:?}", from))? {
+        let e = e?;
+        let path = e.path();
+        let target = to.join(e.file_name());
+        let ft = e.file_type()?;
+        if ft.is_symlink() {
+            ctx.retry(|| unix::fs::symlink(fs::read_link(&path)?, &target))?;
+        } else if ft.is_dir() {
+            copy_dir(&path, &target, ctx)?;
+        } else if ft.is_file() {
+            ctx.retry(|| fs::copy(&path, &target))
+                .with_context(|| format!("could not copy {:?}", &path))?;
+        } else {
+            match ctx.conf.special_files {
+                SpecialFiles::Skip => {
+                    report::warning(format!("skipping special file: {:?}", &path))
+                }
+                SpecialFiles::Error => {
+                    return Err(anyhow!(
+                        "cannot copy {:?}. it is a socket, fifo or device. move it out of {:?} or set special_files = \"skip\"",
+                        &path,
+                        from
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn generate_entry_set(parent_dir: impl AsRef<Path>) -> Result<HashSet<PathBuf>> {
     let mut set = HashSet::new();
+    walk_entries(parent_dir, |p| {
+        set.insert(p);
+        Ok(())
+    })?;
+    Ok(set)
+}
 
-    let mut dir_buff = Vec::new();
-    let mut dir_buff_iter = vec![parent_dir.as_ref().to_path_buf()];
-
-    while !dir_buff_iter.is_empty() {
-        for dir in dir_buff_iter.iter() {
-            for e in fs::read_dir(dir)? {
-                let e = e?;
-                let ft = e.file_type()?;
-                let p = e.path();
-                let rel_path = p.strip_prefix(&parent_dir)?.to_path_buf();
-
-                if ft.is_file() {
-                    set.insert(rel_path);
-                } else if ft.is_dir() {
-                    if p.join(STUB).exists() {
-                        set.insert(rel_path);
-                    } else {
-                        dir_buff.push(p);
-                    }
-                } else if ft.is_symlink() {
-                    println!(
-                        "Warning: ignoring symlink: {}\n",
-                        e.path().to_string_lossy()
-                    );
+/// the names of the symlinks in dir that are entries (see LINKS)
+pub fn tracked_links(dir: &Path) -> Result<HashSet<String>> {
+    let path = dir.join(LINKS);
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    let links = fs::read_to_string(&path).with_context(|| format!("could not read {:?}", &path))?;
+    Ok(links
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// records (or forgets) that the symlink is an entry. LINKS is deleted once it is empty
+pub fn set_tracked_link(link: &Path, tracked: bool) -> Result<()> {
+    let dir = link.parent().expect("must have a parent");
+    let mut links = tracked_links(dir)?;
+    let changed = match tracked {
+        true => links.insert(link.name().to_owned()),
+        false => links.remove(link.name()),
+    };
+    if !changed {
+        return Ok(());
+    }
+    let path = dir.join(LINKS);
+    if links.is_empty() {
+        return Ok(fs::remove_file(&path)?);
+    }
+    let mut links = links.into_iter().collect::<Vec<_>>();
+    links.sort();
+    fs::write(&path, links.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// calls f with the path (relative to parent_dir) of every entry under parent_dir.
+/// the tree is walked depth first, so only the dirs that are yet to be visited are
+/// kept in memory (not every entry)
+pub fn walk_entries(
+    parent_dir: impl AsRef<Path>,
+    mut f: impl FnMut(PathBuf) -> Result<()>,
+) -> Result<()> {
+    let parent_dir = parent_dir.as_ref();
+    let mut pending = vec![parent_dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let links = tracked_links(&dir)?;
+        for e in fs::read_dir(&dir)? {
+            let e = e?;
+            let ft = e.file_type()?;
+            let p = e.path();
+
+            if e.file_name() == LINKS {
+                continue;
+            }
+            // symlinks added with --track-symlink are entries too (never followed)
+            let tracked = e.file_name().to_str().is_some_and(|n| links.contains(n));
+            if ft.is_file() || (ft.is_symlink() && tracked) {
+                f(p.strip_prefix(parent_dir)?.to_path_buf())?;
+            } else if ft.is_symlink() {
+                report::warning(format!("ignoring symlink: {}", p.to_string_lossy()));
+            } else if ft.is_dir() {
+                if p.join(STUB).exists() {
+                    f(p.strip_prefix(parent_dir)?.to_path_buf())?;
                 } else {
-                    println!("ignoring path: {}\n", e.path().to_string_lossy());
+                    pending.push(p);
                 }
+            } else {
+                report::skipped(format!("ignoring path: {}", p.to_string_lossy()));
             }
         }
+    }
+
+    Ok(())
+}
 
-        dir_buff_iter.clear();
-        std::mem::swap(&mut dir_buff, &mut dir_buff_iter);
+/// makes path (and everything in it) owned, readable and writable by the non root user,
+/// so that the repo can be used without root. returns the paths that were changed
+pub fn fix_permissions(path: &Path, ctx: &Ctx) -> Result<Vec<PathBuf>> {
+    let uid = ctx.non_root_user.uid();
+    let gid = ctx.non_root_user.primary_group_id();
+    let mut fixed = Vec::new();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(p) = pending.pop() {
+        let meta = fs::symlink_metadata(&p)?;
+        if meta.file_type().is_symlink() {
+            continue;
+        }
+        let wanted = match meta.is_dir() {
+            true => 0o700,
+            false => 0o600,
+        };
+        let chown = meta.uid() != uid;
+        let chmod = meta.mode() & wanted != wanted;
+        if chown || chmod {
+            report::action(Kind::Created, "fixing permissions", &[("path", &p)]);
+            ctx.with_privileges_if(chown, || {
+                if chown {
+                    unix::fs::chown(&p, Some(uid), Some(gid))?;
+                }
+                let mode = (meta.mode() | wanted) & 0o7777;
+                fs::set_permissions(&p, fs::Permissions::from_mode(mode))?;
+                Ok(())
+            })
+            .with_context(|| format!("could not give {:?} to the user", &p))?;
+            fixed.push(p.clone());
+        }
+        if meta.is_dir() {
+            for e in fs::read_dir(&p)? {
+                pending.push(e?.path());
+            }
+        }
     }
+    Ok(fixed)
+}
 
-    Ok(set)
+/// finds the most recent dump of the entry made while the profile was active
+pub fn latest_dump(e: &Entry, profile: &str, ctx: &Ctx) -> Result<Option<PathBuf>> {
+    let dumps = Ctx::dumps_of(&ctx.config_dir, profile);
+    if !dumps.exists() {
+        return Ok(None);
+    }
+
+    let mut dirs = fs::read_dir(dumps)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    // dump dir names sort in the order they were created in
+    dirs.sort();
+
+    let relative = e.dump_relative(ctx.conf.dump_layout);
+    for d in dirs.into_iter().rev() {
+        // dump archives are searched no matter the current dump_format
+        if d.is_file() && d.to_string_lossy().ends_with(dump_archive::EXT) {
+            if dump_archive::contains(&d, &relative)? {
+                return Ok(Some(d.join(&relative)));
+            }
+            continue;
+        }
+        let p = d.join(&relative);
+        if p.exists() || p.is_symlink() {
+            return Ok(Some(p));
+        }
+    }
+    Ok(None)
+}
+
+/// canonicalize, but symlink loops give an error that shows the loop
+pub fn canonicalize(path: impl AsRef<Path>) -> Result<PathBuf> {
+    let path = path.as_ref();
+    match path.canonicalize() {
+        Ok(p) => Ok(p),
+        Err(e) if is_eloop(&e) => Err(loop_error(path)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// true if path is a symlink that can not be resolved because of a loop
+pub fn is_symlink_loop(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    path.is_symlink() && matches!(path.canonicalize(), Err(e) if is_eloop(&e))
+}
+
+fn is_eloop(e: &std::io::Error) -> bool {
+    e.raw_os_error() == Some(Errno::ELOOP as i32)
+}
+
+fn loop_error(path: &Path) -> anyhow::Error {
+    // the outermost symlink that loops
+    let at = path
+        .ancestors()
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .find(|p| is_symlink_loop(p))
+        .unwrap_or(path);
+
+    let mut chain = vec![at.to_path_buf()];
+    let mut p = at.to_path_buf();
+    // the kernel gives up after 40 links too
+    for _ in 0..40 {
+        let Ok(target) = fs::read_link(&p) else {
+            break;
+        };
+        p = p.parent().map(|d| d.join(&target)).unwrap_or(target);
+        let looped = chain.contains(&p);
+        chain.push(p.clone());
+        if looped {
+            break;
+        }
+    }
+    let chain = chain
+        .iter()
+        .map(|p| format!("{:?}", p))
+        .collect::<Vec<_>>()
+        .join(" -> ");
+    anyhow!("symlink loop detected at path {:?}\n  {}", at, chain)
+}
+
+/// bytes taken by the files in path (symlinks are not followed)
+pub fn path_size(path: impl AsRef<Path>) -> Result<u64> {
+    let path = path.as_ref();
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut size = 0;
+    for e in fs::read_dir(path)? {
+        size += path_size(e?.path())?;
+    }
+    Ok(size)
+}
+
+/// false if anything can not be read (like a file of root that is in the way). paths are
+/// only hashed if they are the same kind of thing (and files of the same size)
+fn same_contents(a: &Path, b: &Path) -> bool {
+    let (Ok(ma), Ok(mb)) = (fs::symlink_metadata(a), fs::symlink_metadata(b)) else {
+        return false;
+    };
+    if ma.file_type() != mb.file_type() || (ma.is_file() && ma.len() != mb.len()) {
+        return false;
+    }
+    match (hash_path(a), hash_path(b)) {
+        (Ok(ha), Ok(hb)) => ha == hb,
+        _ => false,
+    }
+}
+
+/// a hash of the contents of a file or a directory (ignoring STUB files)
+pub fn hash_path(path: impl AsRef<Path>) -> Result<String> {
+    stats::count_hash();
+    fn update(hasher: &mut blake3::Hasher, root: &Path, path: &Path) -> Result<()> {
+        let meta = fs::symlink_metadata(path)?;
+        hasher.update(path.strip_prefix(root)?.as_os_str().as_encoded_bytes());
+        if meta.is_symlink() {
+            hasher.update(b"l");
+            hasher.update(fs::read_link(path)?.as_os_str().as_encoded_bytes());
+        } else if meta.is_dir() {
+            hasher.update(b"d");
+            let mut children = fs::read_dir(path)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            children.sort();
+            for child in children.iter().filter(|c| c.name() != STUB) {
+                update(hasher, root, child)?;
+            }
+        } else if meta.is_file() {
+            hasher.update(b"f");
+            hasher.update_reader(fs::File::open(path)?)?;
+        } else {
+            // opening a fifo would block
+            hasher.update(b"s");
+        }
+        Ok(())
+    }
+
+    let path = path.as_ref();
+    let mut hasher = blake3::Hasher::new();
+    update(&mut hasher, path, path)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// hashes of the entries materialized by copying, keyed by src
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HashState {
+    pub hashes: HashMap<PathBuf, String>,
+}
+
+impl HashState {
+    fn path(ctx: &Ctx, module: &str) -> PathBuf {
+        ctx.config_dir
+            .join("state")
+            .join(format!("{}.toml", module))
+    }
+
+    pub fn load(ctx: &Ctx, module: &str) -> Result<Self> {
+        let path = Self::path(ctx, module);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, ctx: &Ctx, module: &str) -> Result<()> {
+        let path = Self::path(ctx, module);
+        fs::create_dir_all(path.parent().expect("must have a parent"))?;
+        fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
 }
 
 pub trait Convenience {
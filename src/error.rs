@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+/// errors that callers might want to handle differently. they are returned inside
+/// anyhow::Error, use downcast_ref to match on them
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("module '{0}' is not active")]
+    ModuleNotActive(String),
+
+    #[error("module '{0}' is read only")]
+    ModuleReadOnly(String),
+
+    #[error("no module with name {0} found in the repo.")]
+    ModuleNotFound(String),
+
+    #[error("profile with name: '{0}' does not exist.")]
+    ProfileNotFound(String),
+
+    #[error("file '{path:?}' not in module '{module}'")]
+    PathNotInModule { path: PathBuf, module: String },
+
+    #[error("path '{path}' is already managed by module '{module}' (links to {target:?})")]
+    AlreadyManaged {
+        path: String,
+        module: String,
+        target: PathBuf,
+    },
+
+    #[error(
+        "there is already a file/dir at: {0:?}. use --if-exists overwrite (or skip) to sync anyway"
+    )]
+    ConflictNeedsForce(PathBuf),
+
+    #[error("No root privileges")]
+    PrivilegeRequired,
+
+    /// is-managed found no module for the path. main exits with 1 without printing it
+    #[error("path '{0}' is not managed by any module")]
+    NotManaged(String),
+}
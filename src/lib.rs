@@ -0,0 +1,1109 @@
+use std::{
+    fs,
+    io::{IsTerminal, Write},
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{Ctx, ProfileDesc};
+use nix::unistd;
+use profile::{IfExists, Profile, SymlinkSrc};
+use report::{Kind, Output};
+
+pub mod archive;
+pub mod batch;
+pub mod config;
+pub mod dump_archive;
+pub mod entry;
+pub mod error;
+pub mod module;
+pub mod oplog;
+pub mod plan;
+pub mod profile;
+pub mod report;
+pub mod snapshot;
+pub mod stats;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    /// Specify a custom config directory
+    #[arg(short, long)]
+    pub config_dir: Option<String>,
+
+    /// Use this config file instead of config.toml in the config directory
+    #[arg(long)]
+    pub config: Option<String>,
+
+    /// Ignore the [defaults] section of the config
+    #[arg(long, global = true, default_value_t = false)]
+    pub no_defaults: bool,
+
+    /// Do not print about paths that are already the way they should be
+    #[arg(long, global = true, default_value_t = false)]
+    pub quiet_skips: bool,
+
+    /// Print each step of how paths are resolved to modules and entries (to stderr)
+    #[arg(long, global = true, default_value_t = false)]
+    pub trace_resolution: bool,
+
+    /// Print how long loading, validating and syncing took at the end (to stderr)
+    #[arg(long, global = true, default_value_t = false)]
+    pub stats: bool,
+
+    /// How results of commands are printed
+    #[arg(long, global = true, value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+
+    /// Print more about what is done (to stderr). -v reports file operations that only
+    /// worked after a retry, -vv every retry
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    #[command(subcommand)]
+    pub command: Command,
+    // #[arg(long = "dry")]
+    // pub dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Add entry to module
+    Add {
+        #[clap(required_unless_present = "from_url")]
+        src: Vec<String>,
+
+        #[clap(long, short)]
+        module: Option<String>,
+
+        /// copy into the module without touching the original (sync links it later)
+        #[clap(long)]
+        copy_only: bool,
+
+        /// add the contents of what a symlink points to instead of the symlink. only the
+        /// symlink is replaced, it's target is left as is
+        #[clap(long, conflicts_with = "copy_only")]
+        dereference: bool,
+
+        /// add a symlink as it is (the link itself is moved into the module)
+        #[clap(long, conflicts_with_all = ["copy_only", "dereference"])]
+        track_symlink: bool,
+
+        /// track the paths as if this dir was / (for staged system roots)
+        #[clap(long)]
+        relative_to: Option<String>,
+
+        /// add the module to the active profile if it is not in it
+        #[clap(long)]
+        activate: bool,
+
+        /// activate the module with lower precedence than this module
+        #[clap(long, requires = "activate", conflicts_with = "after")]
+        before: Option<String>,
+
+        /// activate the module with higher precedence than this module
+        #[clap(long, requires = "activate")]
+        after: Option<String>,
+
+        /// allow adding paths that are denied (like / or /etc/passwd)
+        #[clap(long)]
+        i_know_what_im_doing: bool,
+
+        /// track the paths as home entries even if they only are in home through a
+        /// symlinked dir (like a bind mount or a dir moved to another disk)
+        #[clap(long, conflicts_with = "as_root")]
+        as_home: bool,
+
+        /// track the paths as system paths even if they are in home (like a home
+        /// outside /home that is shared with other machines as a system dir)
+        #[clap(long)]
+        as_root: bool,
+
+        /// commit the added paths to this branch of the repo (without checking it out)
+        #[clap(long)]
+        git_branch: Option<String>,
+
+        /// download the file at this url (http/https) to the path given by --as and add it
+        #[clap(long, requires = "as_path", conflicts_with_all = ["src", "copy_only", "relative_to"])]
+        from_url: Option<String>,
+
+        /// where the downloaded file is linked to
+        #[clap(long = "as", requires = "from_url")]
+        as_path: Option<String>,
+
+        /// add every file in the dirs as a separate entry instead of the dirs as a whole
+        #[clap(long, conflicts_with = "from_url")]
+        expand: bool,
+
+        /// only add files matching one of these globs (with --expand)
+        #[clap(long, requires = "expand")]
+        only: Vec<String>,
+
+        /// do not add files matching one of these globs (with --expand)
+        #[clap(long, requires = "expand")]
+        except: Vec<String>,
+    },
+
+    // - [Support for using ArgGroup as Enum with derive](https://github.com/clap-rs/clap/issues/2621#issuecomment-1074671496)
+    /// Remove entry from module
+    #[clap(group = clap::ArgGroup::new("module-method").multiple(false).required(true))]
+    Remove {
+        #[clap(required = true)]
+        src: Vec<String>,
+
+        // specify module to remove the entry from
+        #[clap(long, short, group = "module-method")]
+        module: Option<String>,
+
+        // remove entry from the active module with highest precedence
+        #[clap(long, short, group = "module-method")]
+        active: bool,
+
+        // remove entry from default module
+        #[clap(long, short, group = "module-method")]
+        default: bool,
+
+        /// do not ask anything. --active picks the module with the highest precedence
+        #[clap(long, short)]
+        yes: bool,
+
+        /// commit the removal to this branch of the repo (without checking it out)
+        #[clap(long)]
+        git_branch: Option<String>,
+
+        /// move the entry to the dump instead of restoring it. src is left empty
+        #[clap(long)]
+        dump_only: bool,
+
+        /// keep the dirs in the module that are left empty by the removal
+        #[clap(long)]
+        keep_dirs: bool,
+        // TODO: choose module with fzf
+        // #[clap(long, short, group = "module-method", default_value_t = true)]
+        // choose: bool,
+    },
+
+    /// Create a new profile
+    NewProfile {
+        /// Name of the new profile
+        name: String,
+    },
+
+    /// Switch to a different profile
+    SwitchProfile {
+        name: String,
+
+        /// overwrite files (deprecated, same as --if-exists overwrite)
+        #[arg(long, short, default_value_t = false)]
+        force: bool,
+
+        /// what to do with files that are in the way of an entry (error by default)
+        #[arg(long, value_enum, conflicts_with = "force")]
+        if_exists: Option<IfExists>,
+
+        /// restore the dumped originals of entries of deactivated modules
+        #[arg(long, default_value_t = false)]
+        restore_dumped: bool,
+
+        /// ask before switching
+        #[arg(long, default_value_t = false)]
+        confirm: bool,
+
+        /// leave entries of deactivated modules that are not linked instead of failing
+        #[arg(long, default_value_t = false)]
+        force_unlink: bool,
+
+        /// only make it the active profile. the next sync applies it
+        #[arg(long, default_value_t = false, conflicts_with_all = ["force", "if_exists", "restore_dumped", "confirm"])]
+        no_sync: bool,
+    },
+
+    /// Check and apply the config (if edited)
+    Sync {
+        /// overwrite files (deprecated, same as --if-exists overwrite)
+        #[arg(long, short, default_value_t = false)]
+        force: bool,
+
+        /// what to do with files that are in the way of an entry (error by default)
+        #[arg(long, value_enum, conflicts_with = "force")]
+        if_exists: Option<IfExists>,
+
+        /// restore the dumped originals of entries of deactivated modules
+        #[arg(long, default_value_t = false)]
+        restore_dumped: bool,
+
+        /// sync a profile given as json or toml instead of one from the config
+        #[arg(long)]
+        profile_inline: Option<String>,
+
+        /// link the modules of every profile in the config at once
+        #[arg(long, default_value_t = false, conflicts_with_all = ["profile_inline", "link_only"])]
+        all_profiles: bool,
+
+        /// sync the remaining entries when one fails and report the failures at the end
+        #[arg(long, default_value_t = false)]
+        keep_going: bool,
+
+        /// only create missing links. errors instead of dumping anything
+        #[arg(long, default_value_t = false, conflicts_with_all = ["force", "if_exists", "restore_dumped"])]
+        link_only: bool,
+
+        /// let this module win every conflict for this sync only
+        #[arg(long, conflicts_with = "link_only")]
+        prefer: Option<String>,
+
+        /// leave entries of deactivated modules that are not linked instead of failing
+        #[arg(long, default_value_t = false)]
+        force_unlink: bool,
+
+        /// list every path that is in the way of the sync without changing anything
+        #[arg(long, default_value_t = false, conflicts_with = "link_only")]
+        check: bool,
+
+        /// only sync the entries whose files in the repo changed between this git ref and
+        /// HEAD (falls back to a full sync if git can not tell)
+        #[arg(long, conflicts_with_all = ["profile_inline", "all_profiles", "link_only", "check"])]
+        since_commit: Option<String>,
+    },
+
+    /// List every change that sync would make in order, without changing anything
+    Plan {
+        /// plan switching to this profile instead of syncing the active one
+        profile: Option<String>,
+
+        /// plan as if sync was run with -f
+        #[arg(long, short, default_value_t = false)]
+        force: bool,
+
+        /// plan as if sync was run with this --if-exists
+        #[arg(long, value_enum, conflicts_with = "force")]
+        if_exists: Option<IfExists>,
+
+        /// restore the dumped originals of entries of deactivated modules
+        #[arg(long, default_value_t = false)]
+        restore_dumped: bool,
+
+        /// leave entries of deactivated modules that are not linked instead of failing
+        #[arg(long, default_value_t = false)]
+        force_unlink: bool,
+    },
+
+    /// Show the state of every entry in the current profile
+    Status {
+        /// delete modules in the repo that are not in any profile (after asking)
+        #[arg(long, default_value_t = false)]
+        prune_orphans: bool,
+
+        /// recreate the stubs of dirs that are linked as a whole
+        #[arg(long, default_value_t = false)]
+        fix: bool,
+
+        /// show the status of this profile instead of the active one (nothing is changed)
+        #[arg(long, conflicts_with_all = ["prune_orphans", "fix"])]
+        profile: Option<String>,
+    },
+
+    /// Look for dirs linked as a whole that lost their stub, and stubs that git ignores
+    Doctor {
+        /// recreate the missing stubs
+        #[arg(long, default_value_t = false)]
+        fix: bool,
+    },
+
+    /// Show what changed in the managed entries since the last sync
+    Diff,
+
+    /// Show how many modules and entries a profile has and the size of it's entries
+    Info {
+        /// profile to show (the active one if not given)
+        profile: Option<String>,
+    },
+
+    /// Exit with 0 if the path is managed by an active module (1 otherwise)
+    IsManaged {
+        path: String,
+
+        /// do not print the module and the path in the repo
+        #[arg(long, short, default_value_t = false)]
+        quiet: bool,
+    },
+
+    /// Remove empty dirs and orphaned stub files from all modules in the repo
+    Gc {
+        /// only show what would be removed
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Rename the stub files and home dirs of every module in the repo
+    Migrate {
+        /// current name of the stub files
+        #[arg(long, default_value = entry::STUB)]
+        from_stub: String,
+
+        /// new name of the stub files
+        #[arg(long, default_value = entry::STUB)]
+        to_stub: String,
+
+        /// current name of the home dirs
+        #[arg(long, default_value = entry::HOME)]
+        from_home: String,
+
+        /// new name of the home dirs
+        #[arg(long, default_value = entry::HOME)]
+        to_home: String,
+
+        /// migrate even if the repo has uncommitted changes
+        #[arg(long, short, default_value_t = false)]
+        force: bool,
+    },
+
+    /// Edit the config or the active profile in $EDITOR
+    Edit {
+        #[arg(value_enum)]
+        what: EditTarget,
+    },
+
+    /// Pack the repo and the config into a tar archive that works in any home
+    Export {
+        /// Path of the archive
+        out: String,
+    },
+
+    /// Unpack an archive made by export and create the config
+    Import {
+        /// Path of the archive
+        archive: String,
+
+        /// Where to put the repo (defaults to ~/.local/share/configma)
+        dest: Option<String>,
+    },
+
+    /// Point the symlinks of the linked modules at the new location of the repo
+    Relink {
+        /// where the repo is now (updates repo in the config). if not given, the repo in
+        /// the config is taken to already be the new location
+        new_repo: Option<String>,
+    },
+
+    /// Delete the symlinks of a module (or of the whole active profile) without changing the
+    /// active profile. sync links them again
+    Unlink {
+        /// module to unlink (every active module if not given)
+        module: Option<String>,
+    },
+
+    /// Track the links into a module that already exist (made by hand or another tool)
+    /// without moving or linking anything. reports what is not cleanly linked
+    Adopt {
+        /// module that the links point into
+        module: String,
+    },
+
+    /// Run a json list of adds and removes (from a file or stdin) in one go, and print what
+    /// happened to each of them as json
+    Batch {
+        /// file with the operations (stdin if not given)
+        file: Option<String>,
+
+        /// run the remaining operations when one fails
+        #[arg(long, default_value_t = false)]
+        keep_going: bool,
+    },
+
+    /// Run the operations recorded in an operations log again (skipping the done ones)
+    Replay {
+        /// the operations log (see operations_log in the config)
+        log: String,
+    },
+
+    /// Clone a config repo, create the config and apply a profile
+    Bootstrap {
+        /// Url of the git repo to clone
+        url: String,
+
+        /// Profile to switch to (defaults to the first profile in the config)
+        #[arg(long, short)]
+        profile: Option<String>,
+
+        /// Where to clone the repo (defaults to ~/.local/share/configma)
+        #[arg(long, short)]
+        repo: Option<String>,
+
+        /// overwrite files
+        #[arg(long, short, default_value_t = false)]
+        force: bool,
+    },
+}
+
+impl Command {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Add { .. } => "add",
+            Command::Remove { .. } => "remove",
+            Command::NewProfile { .. } => "new-profile",
+            Command::SwitchProfile { .. } => "switch-profile",
+            Command::Sync { .. } => "sync",
+            Command::Plan { .. } => "plan",
+            Command::Status { .. } => "status",
+            Command::Doctor { .. } => "doctor",
+            Command::Diff => "diff",
+            Command::Info { .. } => "info",
+            Command::IsManaged { .. } => "is-managed",
+            Command::Gc { .. } => "gc",
+            Command::Migrate { .. } => "migrate",
+            Command::Edit { .. } => "edit",
+            Command::Export { .. } => "export",
+            Command::Import { .. } => "import",
+            Command::Relink { .. } => "relink",
+            Command::Unlink { .. } => "unlink",
+            Command::Adopt { .. } => "adopt",
+            Command::Batch { .. } => "batch",
+            Command::Replay { .. } => "replay",
+            Command::Bootstrap { .. } => "bootstrap",
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum EditTarget {
+    /// config.toml
+    Config,
+    /// the active profile file
+    Profile,
+}
+
+pub fn confirm(msg: impl AsRef<str>) -> Result<bool> {
+    print!("{} [y/N] ", msg.as_ref());
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// asks the user to pick one of the options by number
+pub fn pick(msg: impl AsRef<str>, options: &[String]) -> Result<usize> {
+    if options.is_empty() {
+        return Err(anyhow!("nothing to pick from"));
+    }
+
+    println!("{}", msg.as_ref());
+    for (i, o) in options.iter().enumerate() {
+        println!("  {}) {}", i + 1, o);
+    }
+    loop {
+        print!("[1-{}] ", options.len());
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Err(anyhow!("nothing picked"));
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(n) if (1..=options.len()).contains(&n) => return Ok(n - 1),
+            _ => continue,
+        }
+    }
+}
+
+/// for commit messages
+fn join_names<P: AsRef<std::path::Path>>(paths: &[P]) -> String {
+    paths
+        .iter()
+        .map(|p| p.as_ref().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// TODO: edit readme to remove stuff about a single file + other stuff
+
+pub fn main() {
+    report::init();
+    let res = run();
+    stats::print();
+    if let Err(err) = res {
+        if !matches!(err.downcast_ref(), Some(error::Error::NotManaged(_))) {
+            report::error(&err);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let (root_u, non_root_u) = if unistd::geteuid().is_root() {
+        let non_root_user = users::get_user_by_name(&std::env::var("SUDO_USER")?)
+            .context("configma must be run as a non root user or using sudo")?;
+        let root_user =
+            users::get_user_by_name(&std::env::var("USER")?).context("USER is not set :/")?;
+
+        // drop effective privileges until required
+        unistd::setegid(unistd::Gid::from_raw(non_root_user.primary_group_id()))?;
+        unistd::seteuid(unistd::Uid::from_raw(non_root_user.uid()))?;
+
+        (Some(root_user), non_root_user)
+    } else {
+        let user =
+            users::get_user_by_name(&std::env::var("USER")?).context("USER is not set :/")?;
+        (None, user)
+    };
+
+    let mut cli = Cli::parse();
+    report::set_quiet_skips(cli.quiet_skips);
+    report::set_trace_resolution(cli.trace_resolution);
+    report::set_verbosity(cli.verbose);
+    // stdout of batch is only the json of the outcomes
+    report::set_to_stderr(matches!(cli.command, Command::Batch { .. }));
+    stats::set_enabled(cli.stats, cli.output);
+    let _total = stats::timer(|| "total".to_owned());
+    if let Command::Bootstrap {
+        url,
+        profile,
+        repo,
+        force,
+    } = &cli.command
+    {
+        let name = Ctx::bootstrap(&cli, &non_root_u, url, repo.as_deref(), profile.as_deref())?;
+        cli.command = Command::SwitchProfile {
+            name,
+            force: *force,
+            if_exists: None,
+            restore_dumped: false,
+            confirm: false,
+            force_unlink: false,
+            no_sync: false,
+        };
+    }
+    if let Command::Import { archive, dest } = &cli.command {
+        return archive::import(&cli, &non_root_u, archive, dest.as_deref());
+    }
+    if let Command::Edit { what } = &cli.command {
+        if !Ctx::edit(&cli, &non_root_u, *what)? {
+            return Ok(());
+        }
+        cli.command = Command::Sync {
+            force: false,
+            if_exists: None,
+            restore_dumped: false,
+            profile_inline: None,
+            all_profiles: false,
+            keep_going: false,
+            link_only: false,
+            prefer: None,
+            force_unlink: false,
+            check: false,
+            since_commit: None,
+        };
+    }
+
+    let old_repo = match &cli.command {
+        Command::Relink {
+            new_repo: Some(new),
+        } => Some(Ctx::set_repo(&cli, &non_root_u, new)?),
+        _ => None,
+    };
+
+    let ctx = Ctx::new(&cli, root_u, non_root_u)?;
+    if !cli.no_defaults {
+        ctx.conf.defaults.apply(&mut cli.command);
+    }
+    if let Command::Export { out } = &cli.command {
+        return archive::export(&ctx, out);
+    }
+    if let Command::Replay { log } = &cli.command {
+        return oplog::replay(&module::Module::resolve_path(log, &ctx)?, &ctx);
+    }
+
+    if !ctx.profile_file.exists() {
+        match &cli.command {
+            Command::NewProfile { name } => {
+                config::validate_name("profile", name)?;
+                let is_module = ctx.repo.join(name).exists()
+                    || ctx.conf.modules.iter().any(|m| &m.name == name);
+                if is_module {
+                    return Err(anyhow!(
+                        "there is already a module named '{}'. pick a different name for the profile",
+                        name
+                    ));
+                }
+                std::fs::create_dir_all(ctx.repo.join(config::PROFILES_DIR).join(name))?;
+                let prof = ProfileDesc {
+                    name: name.to_owned(),
+                    modules: Default::default(),
+                    absent: Default::default(),
+                };
+                fs::write(&ctx.profile_file, toml::to_string_pretty(&prof)?)?;
+
+                return Ok(());
+            }
+            Command::SwitchProfile { name, .. } => {
+                let Some(_) = ctx.conf.profiles.iter().find(|p| p.name.as_str() == name) else {
+                    return Err(error::Error::ProfileNotFound(name.clone()).into());
+                };
+                let prof = ProfileDesc {
+                    name: name.to_owned(),
+                    modules: Default::default(),
+                    absent: Default::default(),
+                };
+                fs::write(&ctx.profile_file, toml::to_string_pretty(&prof)?)?;
+            }
+            _ => return Err(anyhow!("Set a profile with switch-profile.")),
+        }
+    }
+
+    let active = fs::read_to_string(&ctx.profile_file)?;
+    let active_conf = toml::from_str::<ProfileDesc>(&active)?;
+
+    let mut profile = match &cli.command {
+        Command::SwitchProfile { name, .. } => {
+            let Some(required) = ctx.conf.profiles.iter().find(|p| p.name.as_str() == name) else {
+                return Err(anyhow!(
+                    "profile with name: '{}' not found in configs. available profiles: {}",
+                    name,
+                    ctx.profile_names()
+                ));
+            };
+
+            Profile::new(active_conf, required.clone(), &ctx)?
+        }
+        Command::Sync {
+            profile_inline: Some(inline),
+            ..
+        } => {
+            let required = ProfileDesc::parse_inline(inline)?;
+            Profile::new(active_conf, required, &ctx)?
+        }
+        Command::Status {
+            profile: Some(name),
+            ..
+        }
+        | Command::Info {
+            profile: Some(name),
+        }
+        | Command::Plan {
+            profile: Some(name),
+            ..
+        } => {
+            let Some(required) = ctx.conf.profiles.iter().find(|p| p.name.as_str() == name) else {
+                return Err(anyhow!(
+                    "profile with name: '{}' not found in configs. available profiles: {}",
+                    name,
+                    ctx.profile_names()
+                ));
+            };
+            Profile::new(active_conf, required.clone(), &ctx)?
+        }
+        Command::Add { .. }
+        | Command::Remove { .. }
+        | Command::NewProfile { .. }
+        | Command::Sync { .. }
+        | Command::Plan { .. }
+        | Command::Status { .. }
+        | Command::Doctor { .. }
+        | Command::Diff
+        | Command::Info { .. }
+        | Command::IsManaged { .. }
+        | Command::Gc { .. }
+        | Command::Migrate { .. }
+        | Command::Edit { .. }
+        | Command::Export { .. }
+        | Command::Import { .. }
+        | Command::Relink { .. }
+        | Command::Unlink { .. }
+        | Command::Adopt { .. }
+        | Command::Batch { .. }
+        | Command::Replay { .. }
+        | Command::Bootstrap { .. } => {
+            let Some(required) = ctx
+                .conf
+                .profiles
+                .iter()
+                .find(|p| p.name == active_conf.name)
+            else {
+                // switch-profile only needs the target profile, so it can be used to recover
+                return Err(anyhow!(
+                    "active profile '{}' is no longer in the configs. switch to a different profile using switch-profile. available profiles: {}",
+                    &active_conf.name,
+                    ctx.profile_names()
+                ));
+            };
+
+            Profile::new(active_conf, required.clone(), &ctx)?
+        }
+    };
+
+    if ctx
+        .conf
+        .default_module
+        .as_ref()
+        .map(|m| !profile.required_conf.modules.contains(m))
+        .unwrap_or(false)
+    {
+        if ctx.conf.require_default_module {
+            return Err(anyhow!("profile must contain the default module."));
+        }
+        report::warning("profile does not contain the default module. use -m to pick a module");
+    }
+
+    match cli.command {
+        Command::NewProfile { .. } => (),
+        Command::Bootstrap { .. } => unreachable!("replaced with switch-profile"),
+        Command::Edit { .. } => unreachable!("replaced with sync"),
+        Command::Export { .. } | Command::Import { .. } | Command::Replay { .. } => {
+            unreachable!("handled earlier")
+        }
+        Command::SwitchProfile {
+            force,
+            if_exists,
+            restore_dumped,
+            confirm: ask,
+            no_sync,
+            ..
+        } => {
+            let if_exists = IfExists::from_flags(force, if_exists);
+            if no_sync {
+                return profile.select(&ctx);
+            }
+            profile.validate()?;
+            profile.print_plan(&ctx)?;
+            if ask && !confirm("switch profile?")? {
+                return Ok(());
+            }
+            let res = profile.sync(if_exists, restore_dumped, false, &ctx);
+            ctx.notify("switch-profile", &res);
+            res?;
+            oplog::Op::SwitchProfile {
+                profile: profile.required_conf.name.clone(),
+            }
+            .record(&ctx)?;
+        }
+        Command::Sync {
+            link_only: true, ..
+        } => {
+            profile.validate()?;
+            profile.link_only(&ctx)?;
+        }
+        Command::Sync {
+            force,
+            if_exists,
+            restore_dumped,
+            keep_going,
+            all_profiles,
+            prefer,
+            check,
+            since_commit,
+            ..
+        } => {
+            let if_exists = IfExists::from_flags(force, if_exists);
+            if all_profiles {
+                profile.required_conf = profile.all_profiles(&ctx)?;
+            }
+            if let Some(name) = prefer {
+                profile.prefer(name)?;
+            }
+            if check {
+                profile.validate()?;
+                return profile.check(&ctx);
+            }
+            if let Some(rev) = since_commit {
+                profile.validate()?;
+                match profile.plan_since(&rev, if_exists, &ctx) {
+                    Ok(actions) => {
+                        let n = actions.len();
+                        let res = profile.apply(actions, keep_going, &ctx);
+                        ctx.notify("sync", &res);
+                        res?;
+                        report::skipped(format!("synced {} entries changed since '{}'", n, rev));
+                        return Ok(());
+                    }
+                    Err(err) => {
+                        report::warning(format!("{:?}\ndoing a full sync", err));
+                    }
+                }
+            }
+            if !all_profiles {
+                profile.warn_changed_modules();
+            }
+            let res = profile
+                .validate()
+                .and_then(|_| profile.sync(if_exists, restore_dumped, keep_going, &ctx));
+            ctx.notify("sync", &res);
+            res?;
+        }
+        Command::Plan {
+            force,
+            if_exists,
+            restore_dumped,
+            ..
+        } => {
+            profile.validate()?;
+            let if_exists = IfExists::from_flags(force, if_exists);
+            plan::print(&profile.plan(if_exists, restore_dumped, &ctx)?, &ctx)?;
+        }
+        Command::Status {
+            prune_orphans, fix, ..
+        } => {
+            profile.status(&ctx)?;
+            if fix {
+                println!();
+                profile.fix_stubs(&ctx)?;
+            }
+            if prune_orphans {
+                println!();
+                profile.prune_orphans(&ctx)?;
+            }
+        }
+        Command::IsManaged { path, quiet } => {
+            let Some((module, dest)) = profile.managed_by(&path, &ctx)? else {
+                return Err(error::Error::NotManaged(path).into());
+            };
+            match (quiet, ctx.output) {
+                (true, _) => (),
+                (false, Output::Json) => {
+                    let out = serde_json::json!({ "module": &module.name, "dest": dest });
+                    println!("{}", out);
+                }
+                (false, _) => println!("{}\t{}", &module.name, dest.to_string_lossy()),
+            }
+        }
+        Command::Info { .. } => profile.info(&ctx)?,
+        Command::Relink { .. } => profile.relink(old_repo.as_deref(), &ctx)?,
+        Command::Unlink { module } => profile.unlink(module.as_deref(), &ctx)?,
+        Command::Adopt { module } => profile.adopt(&module, &ctx)?,
+        Command::Doctor { fix } => profile.doctor(fix, &ctx)?,
+        Command::Batch { file, keep_going } => {
+            let input = match file {
+                Some(file) => {
+                    let path = module::Module::resolve_path(&file, &ctx)?;
+                    fs::read_to_string(&path)
+                        .with_context(|| format!("could not read {:?}", &path))?
+                }
+                None => std::io::read_to_string(std::io::stdin())?,
+            };
+            batch::run(&mut profile, &input, keep_going, &ctx)?;
+        }
+        Command::Diff => {
+            let Some(last) = snapshot::Snapshot::latest(&ctx)? else {
+                return Err(anyhow!("no snapshot found. run sync first"));
+            };
+            last.diff(&snapshot::Snapshot::take(&profile, &ctx))
+                .print(&ctx)?;
+        }
+        Command::Gc { dry_run } => {
+            // the clones of git modules are not ours to change
+            let mut modules = profile
+                .modules
+                .values()
+                .filter(|m| !m.read_only)
+                .collect::<Vec<_>>();
+            modules.sort_by(|a, b| a.name.cmp(&b.name));
+
+            let mut cleaned = 0;
+            for m in modules {
+                cleaned += m.gc(dry_run)?;
+            }
+            match (cleaned, dry_run) {
+                (0, _) => report::skipped("nothing to clean"),
+                (n, true) => report::skipped(format!("would remove {} paths", n)),
+                (n, false) => report::skipped(format!("removed {} paths", n)),
+            }
+        }
+        Command::Migrate {
+            from_stub,
+            to_stub,
+            from_home,
+            to_home,
+            force,
+        } => {
+            for name in [&to_stub, &to_home] {
+                config::validate_name("new", name.trim_start_matches('.'))?;
+            }
+            if !force && ctx.repo_is_dirty()? {
+                return Err(anyhow!(
+                    "the repo has uncommitted changes. commit them or use -f flag to migrate anyway"
+                ));
+            }
+
+            // the clones of git modules are not ours to change
+            let mut modules = profile
+                .modules
+                .values()
+                .filter(|m| !m.read_only)
+                .collect::<Vec<_>>();
+            modules.sort_by(|a, b| a.name.cmp(&b.name));
+
+            // check every module before renaming anything
+            let mut renames = Vec::new();
+            for m in modules {
+                renames.extend(m.migrate_renames((&from_stub, &to_stub), (&from_home, &to_home))?);
+            }
+            for (from, to) in renames.iter() {
+                report::action(Kind::Created, "renaming", &[("from", from), ("to", to)]);
+                fs::rename(from, to)?;
+            }
+            match renames.len() {
+                0 => report::skipped("nothing to migrate"),
+                n => report::skipped(format!("renamed {} paths", n)),
+            }
+        }
+        Command::Remove {
+            src,
+            module,
+            active,
+            default: def,
+            yes,
+            git_branch,
+            dump_only,
+            ..
+        } => {
+            if let Some(branch) = git_branch.as_ref() {
+                ctx.branch_head(branch)?;
+            }
+            let interactive = !yes && std::io::stdin().is_terminal();
+            let filter = match (module.as_ref(), def) {
+                (Some(name), _) => Some(name.as_str()),
+                (None, true) => ctx.conf.default_module.as_deref(),
+                (None, false) => None,
+            };
+            let mut targets = Vec::new();
+            for src in src.iter() {
+                targets.extend(profile.expand_tracked(src, &ctx, filter)?);
+            }
+
+            if targets.len() > 1
+                && interactive
+                && !confirm(format!("remove {} entries?", targets.len()))?
+            {
+                return Ok(());
+            }
+
+            let mut removed = Vec::new();
+            for src in targets.iter() {
+                let src = src.to_string_lossy();
+                let dest = if let Some(name) = module.as_ref() {
+                    profile.remove(&src, &ctx, name, dump_only)?
+                } else if def {
+                    let name = ctx.conf.default_module.as_ref().context(
+                        "no module specified. set default_module in configs or use -m flag",
+                    )?;
+                    profile.remove(&src, &ctx, name, dump_only)?
+                } else if active {
+                    profile.remove_from_active(&src, &ctx, interactive, dump_only)?
+                } else {
+                    unreachable!()
+                };
+                if let Some(m) = profile
+                    .modules
+                    .values()
+                    .find(|m| dest.starts_with(&m.module_dir))
+                {
+                    if let Ok(e) = m.entry_from_dest(&dest, &ctx) {
+                        oplog::Op::remove(&m.name, &e.src, &ctx).record(&ctx)?;
+                    }
+                }
+                removed.push(dest);
+            }
+            if targets.len() > 1 {
+                report::skipped(format!("removed {} entries", targets.len()));
+            }
+            if let Some(branch) = git_branch.as_ref() {
+                ctx.commit_to_branch(
+                    branch,
+                    &removed,
+                    &format!("configma: remove {}", join_names(&targets)),
+                )?;
+            }
+        }
+        Command::Add {
+            src,
+            module: name,
+            copy_only,
+            dereference,
+            track_symlink,
+            relative_to,
+            activate,
+            before,
+            after,
+            git_branch,
+            from_url,
+            as_path,
+            expand,
+            only,
+            except,
+            ..
+        } => {
+            if let Some(branch) = git_branch.as_ref() {
+                ctx.branch_head(branch)?;
+            }
+            let interactive = std::io::stdin().is_terminal();
+            let name = match (name, ctx.conf.default_module.as_ref()) {
+                (Some(name), _) => name,
+                (None, Some(def)) if !interactive || profile.active_conf.modules.contains(def) => {
+                    def.clone()
+                }
+                (None, _) if interactive => profile.pick_module()?,
+                (None, _) => {
+                    return Err(anyhow!(
+                        "no module specified. set default_module in configs or use -m flag"
+                    ))
+                }
+            };
+            let name = &name;
+            if activate {
+                profile.activate(name, before.as_deref(), after.as_deref(), &ctx)?;
+            }
+            let mut added = Vec::new();
+            if let (Some(url), Some(path)) = (from_url.as_ref(), as_path.as_ref()) {
+                added.extend(profile.add_from_url(url, path, &ctx, name)?);
+            }
+            // dirs are always added file by file to such modules
+            let expand = expand
+                || profile
+                    .modules
+                    .get(name)
+                    .map(|m| m.dir_policy == config::DirPolicy::Individual)
+                    .unwrap_or(false);
+            let mut paths = Vec::new();
+            for src in src.iter() {
+                match expand {
+                    true => paths.extend(
+                        Profile::expand_dir(src, &ctx, &only, &except)?
+                            .into_iter()
+                            .map(|p| p.to_string_lossy().into_owned()),
+                    ),
+                    false => paths.push(src.clone()),
+                }
+            }
+            for src in paths.iter() {
+                let dest = profile.add(
+                    src,
+                    &ctx,
+                    name,
+                    copy_only,
+                    match (dereference, track_symlink) {
+                        (true, _) => SymlinkSrc::Dereference,
+                        (_, true) => SymlinkSrc::Track,
+                        _ => SymlinkSrc::Refuse,
+                    },
+                    relative_to.as_deref(),
+                )?;
+                // staged paths are not where they are added from, and forced paths would be
+                // guessed again when the log is replayed
+                if let (Some(_), None, None) = (&dest, &relative_to, &ctx.classify_as) {
+                    let path = module::Module::resolve_path(src, &ctx)?;
+                    oplog::Op::add(name, &path, &ctx).record(&ctx)?;
+                }
+                added.extend(dest);
+            }
+            if let (Some(branch), false) = (git_branch.as_ref(), added.is_empty()) {
+                ctx.commit_to_branch(
+                    branch,
+                    &added,
+                    &format!("configma: add {}", join_names(&added)),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,15 +1,23 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeSet, HashSet},
     fs,
     path::{Path, PathBuf},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use nix::unistd;
 use serde::{Deserialize, Serialize};
+use users::os::unix::UserExt;
 
 use crate::{
-    config::Ctx,
-    entry::{generate_entry_set, Convenience, Entry, RelativePath, HOME},
+    config::{validate_name, Ctx, DirPolicy},
+    entry::{
+        canonicalize, generate_entry_set, latest_dump, tracked_links, walk_entries, Convenience,
+        Entry, EntryState, LinkMode, RelativePath, HOME, LINKS, STUB,
+    },
+    plan::SyncAction,
+    report::{self, Kind},
+    stats,
 };
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -18,52 +26,146 @@ pub struct Module {
     pub module_dir: PathBuf,
     pub home_entries: HashSet<PathBuf>,
     pub non_home_entries: HashSet<PathBuf>,
+    pub mode: LinkMode,
+    pub dir_policy: DirPolicy,
+    /// entries can not be added to or removed from this module
+    pub read_only: bool,
+    /// why the module is not linked on this machine (see ModuleDesc::requires)
+    pub unmet: Option<String>,
+    /// dir in the module that the home entries are in (HOME unless rehomed)
+    pub home: PathBuf,
+    /// (entry, extra src that the entry is linked to)
+    pub aliases: Vec<(RelativePath, RelativePath)>,
+    /// the user whose home the home entries are linked into (see ModuleDesc::user)
+    pub owner: Option<Owner>,
 }
 
+/// another user that a module links into the home of
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Owner {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: PathBuf,
+}
+
+impl Owner {
+    pub fn new(name: &str) -> Result<Self> {
+        let Some(user) = users::get_user_by_name(name) else {
+            return Err(anyhow!("user '{}' does not exist", name));
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            uid: user.uid(),
+            gid: user.primary_group_id(),
+            home: canonicalize(user.home_dir())
+                .with_context(|| format!("could not resolve the home dir of user '{}'", name))?,
+        })
+    }
+
+    /// gives the path (and everything in it) to this user. symlinks are not followed
+    pub fn chown(&self, path: &Path) -> Result<()> {
+        unistd::fchownat(
+            None,
+            path,
+            Some(unistd::Uid::from_raw(self.uid)),
+            Some(unistd::Gid::from_raw(self.gid)),
+            unistd::FchownatFlags::NoFollowSymlink,
+        )
+        .with_context(|| format!("could not give {:?} to user '{}'", path, &self.name))?;
+        if !path.is_symlink() && path.is_dir() {
+            for e in fs::read_dir(path)? {
+                self.chown(&e?.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
 pub enum PathResolutionError {
     InRepo,
     OutsideRepo,
+    NotAbsolute,
+    /// forced to be a home entry, but not in home
+    NotInHome,
+}
+
+/// how a path given to add is tracked (instead of guessing from where it is)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PathKind {
+    Home,
+    Root,
 }
 
 impl Module {
     pub fn new(name: String, repo: impl AsRef<Path>) -> Result<Self> {
+        let _timer = stats::timer(|| format!("load module '{}'", &name));
+        validate_name("module", &name)?;
         let repo = repo.as_ref();
         if !repo.exists() {
             return Err(anyhow!("path does not exist: {:?}", repo));
         }
+        if !repo.is_dir() {
+            return Err(anyhow!(
+                "module {}'s path {:?} is not a directory",
+                &name,
+                repo
+            ));
+        }
         let module_dir = repo.join(&name);
 
         if !module_dir.exists() {
             return Err(anyhow!("path does not exist: {:?}", module_dir));
         }
-
-        let home = module_dir.join(HOME);
-        if !home.exists() {
-            fs::create_dir(&home)?;
+        if !module_dir.is_dir() {
+            return Err(anyhow!(
+                "module {} at {:?} is not a directory",
+                &name,
+                module_dir
+            ));
+        }
+        if let Err(err) = fs::read_dir(&module_dir) {
+            if err.kind() == std::io::ErrorKind::PermissionDenied {
+                return Err(anyhow!(
+                    "module {} at {:?} is not readable by the current user",
+                    &name,
+                    module_dir
+                ));
+            }
+            return Err(err).with_context(|| format!("could not read module {}", &name));
         }
 
-        let home_entries = generate_entry_set(home)?;
+        // home/ is created by the first add of a home entry
+        let home = module_dir.join(HOME);
+        let home_entries = match home.exists() {
+            true => generate_entry_set(home)?,
+            false => HashSet::new(),
+        };
 
         let mut entries = HashSet::new();
+        let links = tracked_links(&module_dir)?;
         for dir in fs::read_dir(&module_dir)? {
             let dir = dir?;
             let path = dir.path();
 
-            if path.name() == HOME {
+            // modules can be git repos of their own
+            if path.name() == HOME || path.name() == ".git" || path.name() == LINKS {
                 continue;
             }
 
-            if path.is_file() {
+            if path.is_symlink() && !links.contains(path.name()) {
+                report::warning(format!("ignoring symlink: {}", path.to_string_lossy()));
+            } else if path.is_file() || path.is_symlink() {
                 entries.insert(path.strip_prefix(&module_dir)?.to_path_buf());
             } else if path.is_dir() {
-                let dir_entries = generate_entry_set(&path)?;
-                entries.extend(
-                    dir_entries
-                        .into_iter()
-                        .map(|p| PathBuf::from(path.file_name().expect("no file name")).join(p)),
-                );
+                let name = PathBuf::from(path.file_name().expect("no file name"));
+                walk_entries(&path, |p| {
+                    entries.insert(name.join(p));
+                    Ok(())
+                })?;
             } else {
-                println!("ignoring unhandlable path: {:?}", &path);
+                report::skipped(format!("ignoring unhandlable path: {:?}", &path));
             }
         }
 
@@ -72,10 +174,48 @@ impl Module {
             module_dir,
             home_entries,
             non_home_entries: entries,
+            mode: LinkMode::default(),
+            dir_policy: DirPolicy::default(),
+            read_only: false,
+            unmet: None,
+            home: PathBuf::from(HOME),
+            aliases: Vec::new(),
+            owner: None,
         };
         Ok(s)
     }
 
+    /// treats the entries stored under home_root (an absolute path, like the home dir of
+    /// the author of the module) as home entries of the current user. the other entries
+    /// keep their absolute paths
+    pub fn rehome(&mut self, home_root: &Path) -> Result<()> {
+        let Ok(home) = home_root.strip_prefix("/") else {
+            return Err(anyhow!(
+                "home_root of module '{}' must be absolute: {:?}",
+                &self.name,
+                home_root
+            ));
+        };
+        if home.as_os_str().is_empty() {
+            return Err(anyhow!("home_root of module '{}' can not be /", &self.name));
+        }
+
+        let all = self
+            .home_entries
+            .drain()
+            .map(|p| self.home.join(p))
+            .chain(self.non_home_entries.drain())
+            .collect::<Vec<_>>();
+        for p in all {
+            match p.strip_prefix(home) {
+                Ok(rel) => self.home_entries.insert(rel.to_path_buf()),
+                Err(_) => self.non_home_entries.insert(p),
+            };
+        }
+        self.home = home.to_path_buf();
+        Ok(())
+    }
+
     pub fn contains(&self, e: &Entry) -> bool {
         match &e.relative {
             RelativePath::Home(p) => self.home_entries.contains(p),
@@ -83,51 +223,314 @@ impl Module {
         }
     }
 
-    pub fn unlink_all(&self, ignore_non_links: bool, ctx: &Ctx) -> Result<()> {
-        for e in
-            self.home_entries
-                .iter()
-                .map(|p| self.entry_from_relative(&RelativePath::Home(p.to_path_buf()), ctx))
-                .chain(self.non_home_entries.iter().map(|p| {
+    /// every entry tracked by this module (and the aliases of the entries)
+    pub fn entries<'a>(&'a self, ctx: &'a Ctx) -> impl Iterator<Item = Entry> + 'a {
+        self.home_entries
+            .iter()
+            .map(|p| self.entry_from_relative(&RelativePath::Home(p.to_path_buf()), ctx))
+            .chain(
+                self.non_home_entries.iter().map(|p| {
                     self.entry_from_relative(&RelativePath::NonHome(p.to_path_buf()), ctx)
-                }))
-        {
-            if !e.src.is_symlink() || e.src.canonicalize()? != e.dest {
-                if ignore_non_links {
+                }),
+            )
+            .chain(
+                self.aliases
+                    .iter()
+                    .filter(|(rel, _)| match rel {
+                        RelativePath::Home(p) => self.home_entries.contains(p),
+                        RelativePath::NonHome(p) => self.non_home_entries.contains(p),
+                    })
+                    .map(|(rel, alias)| self.alias_entry(rel, alias, ctx)),
+            )
+    }
+
+    /// links the entry at path to the alias paths too (once the entry is tracked)
+    pub fn add_aliases(&mut self, path: &str, aliases: &[String], ctx: &Ctx) -> Result<()> {
+        let e = self.alias_path(path, ctx)?;
+        for alias in aliases {
+            let a = self.alias_path(alias, ctx)?;
+            self.aliases.push((e.relative.clone(), a.relative));
+        }
+        Ok(())
+    }
+
+    fn alias_path(&self, path: &str, ctx: &Ctx) -> Result<Entry> {
+        let p =
+            shellexpand::tilde_with_context(path, || Some(self.home_dir(ctx).to_string_lossy()));
+        self.entry_from_src(p.as_ref(), ctx).map_err(|_| {
+            anyhow!(
+                "alias path of module '{}' must be absolute (or start with '~') and outside the repo: {}",
+                &self.name,
+                path
+            )
+        })
+    }
+
+    /// entries at the alias srcs of the entry rel
+    pub fn aliases_of<'a>(
+        &'a self,
+        rel: &'a RelativePath,
+        ctx: &'a Ctx,
+    ) -> impl Iterator<Item = Entry> + 'a {
+        self.aliases
+            .iter()
+            .filter(move |(r, _)| r == rel)
+            .map(|(rel, alias)| self.alias_entry(rel, alias, ctx))
+    }
+
+    /// the src is the alias, the rest (except the relative path used for dumps) is from rel
+    fn alias_entry(&self, rel: &RelativePath, alias: &RelativePath, ctx: &Ctx) -> Entry {
+        Entry {
+            dest: self.entry_from_relative(rel, ctx).dest,
+            ..self.entry_from_relative(alias, ctx)
+        }
+    }
+
+    /// what unlinking every entry does. restore_from is the profile whose dumps are restored.
+    /// with ctx.force_unlink, entries that are not linked are left alone instead of failing.
+    /// broken symlinks into the module are deleted
+    pub fn unlink_actions(
+        &self,
+        ignore_non_links: bool,
+        restore_from: Option<&str>,
+        ctx: &Ctx,
+    ) -> Result<Vec<SyncAction>> {
+        let mut actions = Vec::new();
+        for e in self.entries(ctx) {
+            let state = e.state()?;
+            if state != EntryState::Linked {
+                if ignore_non_links || (ctx.force_unlink && state == EntryState::Missing) {
                     continue;
+                }
+                if !ctx.force_unlink {
+                    return Err(anyhow!(
+                        "bad Entry: {:?}. use --force-unlink to leave it as it is",
+                        &e.relative
+                    ));
+                }
+
+                let broken = !e.src.exists()
+                    && fs::read_link(&e.src)
+                        .map(|t| t.starts_with(&self.module_dir))
+                        .unwrap_or(false);
+                actions.push(match broken {
+                    true => SyncAction::DeleteBroken { entry: e },
+                    false => SyncAction::Leave { entry: e },
+                });
+                continue;
+            }
+
+            let restore = match restore_from {
+                Some(profile) => latest_dump(&e, profile, ctx)?,
+                None => None,
+            };
+            actions.push(SyncAction::Unlink { entry: e, restore });
+        }
+        Ok(actions)
+    }
+
+    /// dirs in this module that are linked as a whole (a symlink at src points at them),
+    /// but have no STUB. their files are treated as separate entries
+    pub fn missing_stubs(&self, ctx: &Ctx) -> BTreeSet<PathBuf> {
+        let home = self.module_dir.join(&self.home);
+        let mut missing = BTreeSet::new();
+        for e in self.entries(ctx) {
+            for (src, dest) in e.src.ancestors().zip(e.dest.ancestors()).skip(1) {
+                if dest == home || dest == self.module_dir {
+                    break;
+                }
+                if src.is_symlink() && src.canonicalize().ok().as_deref() == Some(dest) {
+                    missing.insert(dest.to_path_buf());
+                }
+            }
+        }
+        missing
+    }
+
+    /// removes empty dirs and STUB files that do not mark an entry. tracked
+    /// entries are never touched. returns the number of paths removed
+    pub fn gc(&self, dry_run: bool) -> Result<usize> {
+        fn walk(m: &Module, dir: &Path, dry_run: bool, cleaned: &mut usize) -> Result<bool> {
+            let rel = dir.strip_prefix(&m.module_dir)?;
+            let is_entry = match rel.strip_prefix(&m.home) {
+                Ok(p) => m.home_entries.contains(p),
+                Err(_) => m.non_home_entries.contains(rel),
+            };
+            if is_entry {
+                return Ok(false);
+            }
+
+            let mut remaining = 0;
+            for e in fs::read_dir(dir)? {
+                let path = e?.path();
+                if path.is_dir() && !path.is_symlink() {
+                    if !walk(m, &path, dry_run, cleaned)? {
+                        remaining += 1;
+                    } else {
+                        m.gc_remove(&path, "empty dir", dry_run)?;
+                        *cleaned += 1;
+                    }
+                } else if path.name() == STUB {
+                    m.gc_remove(&path, "orphaned stub", dry_run)?;
+                    *cleaned += 1;
                 } else {
-                    return Err(anyhow!("bad Entry: {:?}.", &e.relative));
+                    remaining += 1;
+                }
+            }
+            Ok(remaining == 0)
+        }
+
+        let mut cleaned = 0;
+        walk(self, &self.module_dir, dry_run, &mut cleaned)?;
+        Ok(cleaned)
+    }
+
+    /// the renames needed to move this module from one STUB/home dir name to another.
+    /// nothing is renamed here
+    pub fn migrate_renames(
+        &self,
+        stub: (&str, &str),
+        home: (&str, &str),
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        fn walk(
+            dir: &Path,
+            stub: (&str, &str),
+            renames: &mut Vec<(PathBuf, PathBuf)>,
+        ) -> Result<()> {
+            for e in fs::read_dir(dir)? {
+                let path = e?.path();
+                if path.is_dir() && !path.is_symlink() {
+                    walk(&path, stub, renames)?;
+                } else if stub.0 != stub.1 && path.name() == stub.0 {
+                    renames.push((path.clone(), path.with_file_name(stub.1)));
                 }
             }
+            Ok(())
+        }
 
-            println!("deleting symlink: {:?}\n", &e.src);
-            e.rm_src_file(ctx)?;
+        let mut renames = Vec::new();
+        walk(&self.module_dir, stub, &mut renames)?;
+
+        let from_home = self.module_dir.join(home.0);
+        if home.0 != home.1 && from_home.is_dir() {
+            renames.push((from_home, self.module_dir.join(home.1)));
         }
 
-        Ok(())
+        for (_, to) in &renames {
+            if to.exists() || to.is_symlink() {
+                return Err(anyhow!("cannot migrate. path already exists: {:?}", to));
+            }
+        }
+        Ok(renames)
     }
 
-    pub fn resolve_path(&self, path: impl AsRef<str>, ctx: &Ctx) -> Result<PathBuf> {
-        let filename = PathBuf::from(
-            shellexpand::tilde_with_context(path.as_ref(), || {
-                Some(ctx.canon_home_dir.to_string_lossy())
-            })
-            .into_owned(),
+    fn gc_remove(&self, path: &Path, what: &str, dry_run: bool) -> Result<()> {
+        if dry_run {
+            report::action(
+                Kind::Skipped,
+                format!("would remove {}", what),
+                &[("path", path)],
+            );
+            return Ok(());
+        }
+
+        report::action(
+            Kind::Dumped,
+            format!("removing {}", what),
+            &[("path", path)],
         );
-        let src = filename
-            .parent()
-            .map(|p| {
-                if p.to_string_lossy().is_empty() {
-                    PathBuf::from("./").canonicalize()
-                } else {
-                    p.canonicalize()
+        if path.is_dir() {
+            fs::remove_dir(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// the user named in a path like ~user/..
+    pub fn tilde_user(path: &str) -> Option<&str> {
+        let name = path.strip_prefix('~')?.split('/').next()?;
+        (!name.is_empty()).then_some(name)
+    }
+
+    fn expand_tilde(path: &str, ctx: &Ctx) -> Result<PathBuf> {
+        match Self::tilde_user(path) {
+            Some(name) => {
+                let Some(user) = users::get_user_by_name(name) else {
+                    return Err(anyhow!("user '{}' does not exist", name));
+                };
+                let rest = &path[1 + name.len()..];
+                Ok(PathBuf::from(format!(
+                    "{}{}",
+                    user.home_dir().to_string_lossy(),
+                    rest
+                )))
+            }
+            None => Ok(PathBuf::from(
+                shellexpand::tilde_with_context(path, || {
+                    Some(ctx.canon_home_dir.to_string_lossy())
+                })
+                .into_owned(),
+            )),
+        }
+    }
+
+    /// the absolute path as it is written, without resolving any symlinks in it
+    pub fn lexical_path(path: impl AsRef<str>, ctx: &Ctx) -> Result<PathBuf> {
+        let filename = Self::expand_tilde(path.as_ref(), ctx)?;
+        let mut lexical = match filename.is_absolute() {
+            true => PathBuf::from("/"),
+            false => std::env::current_dir()?,
+        };
+        for c in filename.components() {
+            match c {
+                std::path::Component::ParentDir => {
+                    lexical.pop();
                 }
-            })
-            .unwrap()?
-            .join(filename.file_name().unwrap());
+                std::path::Component::Normal(c) => lexical.push(c),
+                _ => (),
+            }
+        }
+        Ok(lexical)
+    }
+
+    pub fn resolve_path(path: impl AsRef<str>, ctx: &Ctx) -> Result<PathBuf> {
+        let filename = Self::expand_tilde(path.as_ref(), ctx)?;
+        report::trace(format!("resolving '{}' -> {:?}", path.as_ref(), &filename));
+        let resolve = |p: &Path| {
+            canonicalize(p).with_context(|| format!("could not resolve path '{}'", path.as_ref()))
+        };
+
+        // the last component is not resolved, so that symlinks themselves can be managed
+        let src = match (filename.parent(), filename.file_name()) {
+            (Some(p), Some(name)) if p.as_os_str().is_empty() => {
+                resolve(Path::new("."))?.join(name)
+            }
+            (Some(p), Some(name)) => resolve(p)?.join(name),
+            // '.', '..', '/' or paths ending in '..'
+            _ => resolve(&filename)?,
+        };
+        if !src.is_absolute() {
+            return Err(anyhow!(
+                "could not resolve '{}' to an absolute path",
+                path.as_ref()
+            ));
+        }
+        report::trace(format!(
+            "  resolved (last component kept as is): {:?}",
+            &src
+        ));
         Ok(src)
     }
 
+    /// the home dir that the home entries are linked into
+    pub fn home_dir<'a>(&'a self, ctx: &'a Ctx) -> &'a Path {
+        match &self.owner {
+            Some(owner) => &owner.home,
+            None => &ctx.canon_home_dir,
+        }
+    }
+
     pub fn entry_from_dest(
         &self,
         dest: impl AsRef<Path>,
@@ -135,16 +538,21 @@ impl Module {
     ) -> Result<Entry, PathResolutionError> {
         let dest = dest.as_ref();
 
-        if !dest.starts_with(&ctx.canon_repo) {
+        // modules with a custom path are not inside the repo
+        if !dest.starts_with(&self.module_dir) {
+            report::trace(format!(
+                "  module '{}': not inside the module dir {:?}",
+                &self.name, &self.module_dir
+            ));
             return Err(PathResolutionError::OutsideRepo);
         }
 
         let relative = dest.strip_prefix(&self.module_dir).unwrap();
-        let (src, relative) = match relative.starts_with(HOME) {
+        let (src, relative) = match relative.starts_with(&self.home) {
             true => {
-                let stripped = relative.strip_prefix(HOME).unwrap().to_path_buf();
+                let stripped = relative.strip_prefix(&self.home).unwrap().to_path_buf();
                 (
-                    ctx.canon_home_dir.join(&stripped),
+                    self.home_dir(ctx).join(&stripped),
                     RelativePath::Home(stripped),
                 )
             }
@@ -154,34 +562,81 @@ impl Module {
             ),
         };
 
-        Ok(Entry {
+        let e = Entry {
             src,
             relative,
             dest: dest.to_path_buf(),
-        })
+            mode: self.mode,
+            module: Some(self.name.clone()),
+        };
+        e.trace(&format!(
+            "module '{}': path is in the module dir",
+            &self.name
+        ));
+        Ok(e)
     }
 
     pub fn entry_from_src(
         &self,
         src: impl AsRef<Path>,
         ctx: &Ctx,
+    ) -> Result<Entry, PathResolutionError> {
+        self.entry_from_src_as(src, None, ctx)
+    }
+
+    /// like entry_from_src, but the path is tracked as kind if it is given
+    pub fn entry_from_src_as(
+        &self,
+        src: impl AsRef<Path>,
+        kind: Option<PathKind>,
+        ctx: &Ctx,
     ) -> Result<Entry, PathResolutionError> {
         let src = src.as_ref();
 
-        if src.starts_with(&ctx.canon_repo) {
+        if src.starts_with(&ctx.canon_repo) || src.starts_with(&self.module_dir) {
+            report::trace(format!(
+                "  module '{}': path is in the repo but not in the module",
+                &self.name
+            ));
             return Err(PathResolutionError::InRepo);
         }
 
-        let (dest, relative) = match src.starts_with(&ctx.canon_home_dir) {
-            true => {
-                let stripped = src.strip_prefix(&ctx.canon_home_dir).unwrap();
+        // home_roots are dirs in the home of the current user
+        let home_root = ctx
+            .home_roots
+            .iter()
+            .filter(|_| self.owner.is_none() && kind != Some(PathKind::Root))
+            .find(|(canon, _)| src.starts_with(canon));
+
+        let home = self.home_dir(ctx);
+        let in_home = src.starts_with(home) && kind != Some(PathKind::Root);
+        if kind == Some(PathKind::Home) && !in_home && home_root.is_none() {
+            report::trace(format!(
+                "  module '{}': path is not inside home {:?}",
+                &self.name, home
+            ));
+            return Err(PathResolutionError::NotInHome);
+        }
+        let (dest, relative) = match (in_home, home_root) {
+            (true, _) => {
+                let stripped = src.strip_prefix(home).unwrap();
                 (
-                    self.module_dir.join(HOME).join(stripped),
+                    self.module_dir.join(&self.home).join(stripped),
                     RelativePath::Home(stripped.to_path_buf()),
                 )
             }
-            false => {
-                let stripped = src.strip_prefix("/").expect("path must be absolute");
+            (false, Some((canon, relative))) => {
+                let stripped = relative.join(src.strip_prefix(canon).unwrap());
+                (
+                    self.module_dir.join(&self.home).join(&stripped),
+                    RelativePath::Home(stripped),
+                )
+            }
+            (false, None) => {
+                let Ok(stripped) = src.strip_prefix("/") else {
+                    report::trace(format!("  module '{}': path is not absolute", &self.name));
+                    return Err(PathResolutionError::NotAbsolute);
+                };
                 (
                     self.module_dir.join(stripped),
                     RelativePath::NonHome(stripped.to_path_buf()),
@@ -189,35 +644,67 @@ impl Module {
             }
         };
 
-        Ok(Entry {
+        let e = Entry {
             src: src.to_path_buf(),
             relative,
             dest,
-        })
+            mode: self.mode,
+            module: Some(self.name.clone()),
+        };
+        let why = match (in_home, home_root) {
+            (true, _) => format!("inside home {:?}", home),
+            (false, Some((canon, _))) => format!("inside home root {:?}", canon),
+            (false, None) => "outside of home".to_owned(),
+        };
+        e.trace(&format!(
+            "module '{}': path is outside the repo, {}",
+            &self.name, why
+        ));
+        Ok(e)
     }
 
     pub fn entry_from_relative(&self, rel: &RelativePath, ctx: &Ctx) -> Entry {
         match rel {
             RelativePath::Home(p) => Entry {
-                src: ctx.canon_home_dir.join(p),
+                src: self.home_dir(ctx).join(p),
                 relative: rel.clone(),
-                dest: self.module_dir.join(HOME).join(p),
+                dest: self.module_dir.join(&self.home).join(p),
+                mode: self.mode,
+                module: Some(self.name.clone()),
             },
             RelativePath::NonHome(p) => Entry {
                 src: PathBuf::from("/").join(p),
                 relative: rel.clone(),
                 dest: self.module_dir.join(p),
+                mode: self.mode,
+                module: Some(self.name.clone()),
             },
         }
     }
 
     pub fn entry(&self, path_str: impl AsRef<str>, ctx: &Ctx) -> Result<Entry> {
-        let path = self.resolve_path(&path_str, ctx)?;
+        let path = Self::resolve_path(&path_str, ctx)?;
         match self.entry_from_dest(&path, ctx) {
             Ok(p) => Ok(p),
             Err(PathResolutionError::OutsideRepo) => match self.entry_from_src(&path, ctx) {
+                // it may have been added with --as-home or --as-root
+                Ok(p) if !self.contains(&p) => {
+                    let lexical = Self::lexical_path(&path_str, ctx)?;
+                    let forced = [(lexical, PathKind::Home), (path, PathKind::Root)]
+                        .into_iter()
+                        .filter_map(|(src, kind)| self.entry_from_src_as(src, Some(kind), ctx).ok())
+                        .find(|e| self.contains(e));
+                    Ok(forced.unwrap_or(p))
+                }
                 Ok(p) => Ok(p),
-                Err(_) => unreachable!(),
+                Err(PathResolutionError::NotAbsolute) => {
+                    Err(anyhow!("path must be absolute: {:?}", &path))
+                }
+                Err(_) => Err(anyhow!(
+                    "path {:?} is in the repo but not in module '{}'",
+                    &path,
+                    &self.name
+                )),
             },
             Err(_) => unreachable!(),
         }
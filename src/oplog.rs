@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Ctx,
+    profile::{IfExists, Profile, SymlinkSrc},
+    report::{self, Kind},
+};
+
+/// an operation recorded in the operations log. paths in home are kept as ~/.., so that
+/// the log can be replayed in a different home
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Op {
+    Add { module: String, path: String },
+    Remove { module: String, path: String },
+    SwitchProfile { profile: String },
+}
+
+enum Outcome {
+    Done,
+    Skipped(String),
+}
+
+impl Op {
+    pub fn add(module: &str, path: &Path, ctx: &Ctx) -> Self {
+        Self::Add {
+            module: module.to_owned(),
+            path: portable(path, ctx),
+        }
+    }
+
+    pub fn remove(module: &str, path: &Path, ctx: &Ctx) -> Self {
+        Self::Remove {
+            module: module.to_owned(),
+            path: portable(path, ctx),
+        }
+    }
+
+    /// appends the op to the operations log (if enabled)
+    pub fn record(&self, ctx: &Ctx) -> Result<()> {
+        let Some(log) = log_path(ctx) else {
+            return Ok(());
+        };
+        if let Some(parent) = log.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .with_context(|| format!("could not open operations log {:?}", &log))?;
+        writeln!(file, "{}", serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Op::Add { module, path } => format!("add {} to '{}'", path, module),
+            Op::Remove { module, path } => format!("remove {} from '{}'", path, module),
+            Op::SwitchProfile { profile } => format!("switch to profile '{}'", profile),
+        }
+    }
+
+    /// ops whose effect is already there are skipped
+    fn apply(&self, ctx: &Ctx) -> Result<Outcome> {
+        match self {
+            Op::Add { module, path } => {
+                let mut profile = Profile::load(None, ctx)?;
+                if profile.tracks(module, path, ctx) {
+                    return Ok(Outcome::Skipped("already in the module".to_owned()));
+                }
+                profile.add(path, ctx, module, false, SymlinkSrc::Refuse, None)?;
+            }
+            Op::Remove { module, path } => {
+                let mut profile = Profile::load(None, ctx)?;
+                if !profile.tracks(module, path, ctx) {
+                    return Ok(Outcome::Skipped("not in the module".to_owned()));
+                }
+                profile.remove(path, ctx, module, false)?;
+            }
+            Op::SwitchProfile { profile: name } => {
+                let profile = Profile::load(Some(name), ctx)?;
+                if profile.active_conf.name == profile.required_conf.name
+                    && profile.active_conf.modules == profile.required_conf.modules
+                {
+                    return Ok(Outcome::Skipped("already the active profile".to_owned()));
+                }
+                profile.validate()?;
+                profile.sync(IfExists::Error, false, false, ctx)?;
+            }
+        }
+        Ok(Outcome::Done)
+    }
+}
+
+fn log_path(ctx: &Ctx) -> Option<PathBuf> {
+    ctx.conf.operations_log.as_ref().map(|p| {
+        PathBuf::from(
+            shellexpand::tilde_with_context(p, || Some(ctx.canon_home_dir.to_string_lossy()))
+                .into_owned(),
+        )
+    })
+}
+
+fn portable(path: &Path, ctx: &Ctx) -> String {
+    match path.strip_prefix(&ctx.canon_home_dir) {
+        Ok(p) => format!("~/{}", p.to_string_lossy()),
+        Err(_) => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// runs every op in the log in order. failed ops do not stop the replay, but make it fail
+pub fn replay(log: &Path, ctx: &Ctx) -> Result<()> {
+    let contents = fs::read_to_string(log).with_context(|| format!("could not read {:?}", log))?;
+    let ops = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            serde_json::from_str::<Op>(l)
+                .with_context(|| format!("bad operation on line {} of {:?}", i + 1, log))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut failed = 0;
+    for (i, op) in ops.iter().enumerate() {
+        let step = format!("[{}/{}] {}", i + 1, ops.len(), op.describe());
+        match op.apply(ctx) {
+            Ok(Outcome::Done) => println!("{}\n", report::paint(Kind::Created, step)),
+            Ok(Outcome::Skipped(why)) => report::skipped(format!("{} ({})", step, why)),
+            Err(err) => {
+                failed += 1;
+                println!(
+                    "{} {:?}\n",
+                    report::paint(Kind::Error, format!("{}: failed.", step)),
+                    err
+                );
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("{} of {} operations failed", failed, ops.len()));
+    }
+    Ok(())
+}
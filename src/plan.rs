@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    config::Ctx,
+    entry::Entry,
+    report::{self, Kind, Output},
+};
+
+/// a single change that a sync makes. Profile::plan works out all of them before anything
+/// is changed, and Profile::apply runs them in order
+#[derive(Debug)]
+pub enum SyncAction {
+    /// deletes the link (or copy) of an entry of a module that is no longer linked. restores
+    /// the dumped path if there is one
+    Unlink {
+        entry: Entry,
+        restore: Option<PathBuf>,
+    },
+    /// deletes a symlink into a module that does not point at anything anymore
+    DeleteBroken { entry: Entry },
+    /// whatever is at the src of an entry that is being unlinked is left (--force-unlink)
+    Leave { entry: Entry },
+    /// the entries of a module whose requirements are not met are not linked
+    SkipModule {
+        module: String,
+        entries: usize,
+        reason: String,
+    },
+    /// links src to dest. with dump, whatever is at src is moved to the dump first
+    Symlink { entry: Entry, dump: bool },
+    /// copies dest to src. with dump, whatever is at src is moved to the dump first
+    Copy { entry: Entry, dump: bool },
+    /// replaces a copy that was not edited since the last sync with the new contents
+    UpdateCopy { entry: Entry },
+    /// remembers the hash of a copy that is already the same as the repo
+    RecordHash { entry: Entry },
+    /// a copy that was edited since the last sync is left as it is
+    KeepEdited { entry: Entry },
+    /// something is in the way of the entry. the entry fails unless the sync is forced
+    Conflict { entry: Entry },
+    /// something is in the way of the entry and is left as it is (--if-exists skip)
+    KeepExisting { entry: Entry },
+    /// moves a path that the profile keeps absent to the dump
+    RemoveAbsent { entry: Entry },
+}
+
+/// how an action is shown by plan
+#[derive(Serialize)]
+struct Step<'a> {
+    action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    module: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    src: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dst: Option<&'a Path>,
+    /// whatever is at src is moved to the dump first
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dump: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restore: Option<&'a Path>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'a str>,
+}
+
+impl SyncAction {
+    pub fn entry(&self) -> Option<&Entry> {
+        match self {
+            SyncAction::SkipModule { .. } => None,
+            SyncAction::Unlink { entry, .. }
+            | SyncAction::DeleteBroken { entry }
+            | SyncAction::Leave { entry }
+            | SyncAction::Symlink { entry, .. }
+            | SyncAction::Copy { entry, .. }
+            | SyncAction::UpdateCopy { entry }
+            | SyncAction::RecordHash { entry }
+            | SyncAction::KeepEdited { entry }
+            | SyncAction::Conflict { entry }
+            | SyncAction::KeepExisting { entry }
+            | SyncAction::RemoveAbsent { entry } => Some(entry),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SyncAction::Unlink { .. } => "unlink",
+            SyncAction::DeleteBroken { .. } => "delete-broken-link",
+            SyncAction::Leave { .. } => "leave",
+            SyncAction::SkipModule { .. } => "skip-module",
+            SyncAction::Symlink { .. } => "symlink",
+            SyncAction::Copy { .. } => "copy",
+            SyncAction::UpdateCopy { .. } => "update-copy",
+            SyncAction::RecordHash { .. } => "record-hash",
+            SyncAction::KeepEdited { .. } => "keep-edited",
+            SyncAction::Conflict { .. } => "conflict",
+            SyncAction::KeepExisting { .. } => "keep-existing",
+            SyncAction::RemoveAbsent { .. } => "remove-absent",
+        }
+    }
+
+    fn kind(&self) -> Kind {
+        match self {
+            SyncAction::Unlink { .. }
+            | SyncAction::DeleteBroken { .. }
+            | SyncAction::RemoveAbsent { .. } => Kind::Dumped,
+            SyncAction::Symlink { .. }
+            | SyncAction::Copy { .. }
+            | SyncAction::UpdateCopy { .. }
+            | SyncAction::RecordHash { .. } => Kind::Created,
+            SyncAction::Leave { .. }
+            | SyncAction::SkipModule { .. }
+            | SyncAction::KeepEdited { .. }
+            | SyncAction::KeepExisting { .. } => Kind::Skipped,
+            SyncAction::Conflict { .. } => Kind::Warning,
+        }
+    }
+
+    fn step(&self) -> Step<'_> {
+        let entry = self.entry();
+        let mut step = Step {
+            action: self.name(),
+            module: entry.and_then(|e| e.module.as_deref()),
+            src: entry.map(|e| e.src.as_path()),
+            dst: None,
+            dump: false,
+            restore: None,
+            reason: None,
+        };
+        match self {
+            SyncAction::Unlink { restore, .. } => step.restore = restore.as_deref(),
+            SyncAction::DeleteBroken { .. } | SyncAction::Leave { .. } => (),
+            SyncAction::SkipModule { module, reason, .. } => {
+                step.module = Some(module);
+                step.reason = Some(reason);
+            }
+            SyncAction::Symlink { entry, dump } | SyncAction::Copy { entry, dump } => {
+                step.dst = Some(&entry.dest);
+                step.dump = *dump;
+            }
+            SyncAction::UpdateCopy { entry }
+            | SyncAction::RecordHash { entry }
+            | SyncAction::KeepEdited { entry }
+            | SyncAction::Conflict { entry }
+            | SyncAction::KeepExisting { entry } => step.dst = Some(&entry.dest),
+            // the dest of an absent entry is only where it would be dumped to
+            SyncAction::RemoveAbsent { .. } => step.dump = true,
+        }
+        step
+    }
+}
+
+/// prints the actions (as a json list with --output json)
+pub fn print(actions: &[SyncAction], ctx: &Ctx) -> Result<()> {
+    if ctx.output == Output::Json {
+        let steps = actions.iter().map(|a| a.step()).collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&steps)?);
+        return Ok(());
+    }
+
+    let width = actions.iter().map(|a| a.name().len()).max().unwrap_or(0);
+    for a in actions {
+        let step = a.step();
+        let mut line = format!(
+            "  {}",
+            report::paint(a.kind(), format!("{:<width$}", step.action))
+        );
+        if let Some(src) = step.src {
+            line.push_str(&format!(" {:?}", src));
+        }
+        if let Some(dst) = step.dst {
+            line.push_str(&format!(" -> {:?}", dst));
+        }
+        if let SyncAction::SkipModule {
+            module,
+            entries,
+            reason,
+        } = a
+        {
+            line.push_str(&format!(" '{}' ({} entries): {}", module, entries, reason));
+        }
+        if step.dump {
+            line.push_str(" (dumps what is there)");
+        }
+        if let Some(restore) = step.restore {
+            line.push_str(&format!(" (restores {:?})", restore));
+        }
+        println!("{}", line);
+    }
+    if actions.is_empty() {
+        println!("  {}", report::paint(Kind::Skipped, "nothing to do"));
+    }
+    println!();
+    Ok(())
+}
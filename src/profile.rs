@@ -1,31 +1,103 @@
 use std::{
     collections::{HashMap, HashSet},
     fs,
-    path::PathBuf,
+    io::IsTerminal,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use nix::unistd;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::{Ctx, ProfileDesc},
-    entry::{Entry, RelativePath, STUB},
-    module::{Module, PathResolutionError},
+    config::{Ctx, DirPolicy, ProfileDesc},
+    confirm,
+    entry::{
+        canonicalize, fix_permissions, hash_path, is_symlink_loop, path_size, set_tracked_link,
+        Convenience, Entry, EntryState, HashState, LinkMode, RelativePath, STUB,
+    },
+    error::Error,
+    module::{Module, Owner, PathKind, PathResolutionError},
+    pick,
+    plan::SyncAction,
+    report::{self, Kind, Output},
+    snapshot::Snapshot,
+    stats,
 };
 
+/// how deep adopt looks for links in home
+const ADOPT_SCAN_DEPTH: usize = 3;
+
+/// what switching profiles does to the modules
+#[derive(Serialize, Debug)]
+pub struct SwitchPlan {
+    pub from: String,
+    pub to: String,
+    pub unlink: Vec<ModuleChange>,
+    pub link: Vec<ModuleChange>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModuleChange {
+    pub module: String,
+    pub entries: usize,
+}
+
+/// what sync does with a file that is in the way of an entry
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IfExists {
+    /// fail (and change nothing for the entry)
+    #[default]
+    Error,
+    /// leave the file as it is and do not link the entry
+    Skip,
+    /// move the file to the dump and link the entry
+    #[value(alias = "backup")]
+    #[serde(alias = "backup")]
+    Overwrite,
+}
+
+impl IfExists {
+    /// -f is the same as --if-exists overwrite
+    pub fn from_flags(force: bool, if_exists: Option<IfExists>) -> Self {
+        match (if_exists, force) {
+            (Some(i), _) => i,
+            (None, true) => IfExists::Overwrite,
+            (None, false) => IfExists::Error,
+        }
+    }
+}
+
+/// what add does with a src that is a symlink (to somewhere outside the repo)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkSrc {
+    Refuse,
+    /// add the contents of what the link points to
+    Dereference,
+    /// add the link itself
+    Track,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Profile {
     pub modules: HashMap<String, Module>,
 
     pub active_conf: ProfileDesc,
     pub required_conf: ProfileDesc,
+
+    /// module that wins every conflict for this run (the profile is not changed)
+    pub prefer: Option<String>,
 }
 
 impl Profile {
     pub fn new(active: ProfileDesc, required: ProfileDesc, ctx: &Ctx) -> Result<Self> {
+        let _timer = stats::timer(|| "load modules".to_owned());
         // get modules.
         // any modules that are in the main repo
         // modules mentioned in the config (probably from some other source)
+        ctx.migrate_profile_dirs()?;
         let mut modules = HashMap::new();
         for e in fs::read_dir(&ctx.canon_repo)? {
             let e = e?;
@@ -33,27 +105,61 @@ impl Profile {
                 continue;
             }
             let name = e.file_name().into_string().expect("non utf name");
+            // hidden dirs (like .git) are not modules
+            if name.starts_with('.') {
+                continue;
+            }
             let module = Module::new(name.to_owned(), &ctx.canon_repo)?;
             modules.insert(name.to_owned(), module);
         }
 
         for e in &ctx.conf.modules {
+            if e.git.is_some() {
+                let mut module = Module::new(e.name.to_owned(), ctx.remote_module(e)?)?;
+                module.mode = e.mode;
+                module.read_only = true;
+                modules.insert(e.name.to_owned(), module);
+                continue;
+            }
             match &e.path {
                 Some(p) => {
                     let p = shellexpand::tilde_with_context(p, || {
                         Some(ctx.canon_home_dir.to_string_lossy())
                     })
                     .to_string();
-                    let module = Module::new(e.name.to_owned(), PathBuf::from(p).canonicalize()?)?;
+                    let path = PathBuf::from(&p).canonicalize().with_context(|| {
+                        format!("could not resolve the path of module {}: {}", &e.name, p)
+                    })?;
+                    let mut module = Module::new(e.name.to_owned(), path)?;
+                    module.mode = e.mode;
                     modules.insert(e.name.to_owned(), module);
                 }
                 None => {
-                    if !modules.contains_key(&e.name) {
+                    let Some(module) = modules.get_mut(&e.name) else {
                         return Err(anyhow!(
                             "no module with name {} found in the repo.",
                             &e.name
                         ));
-                    }
+                    };
+                    module.mode = e.mode;
+                }
+            }
+        }
+        for e in &ctx.conf.modules {
+            if let Some(module) = modules.get_mut(&e.name) {
+                module.unmet = e.unmet_requirement(ctx);
+                module.dir_policy = e.dir_policy;
+                if let Some(root) = &e.home_root {
+                    module.rehome(Path::new(root))?;
+                }
+                if let Some(user) = &e.user {
+                    module.owner = Some(
+                        Owner::new(user)
+                            .with_context(|| format!("bad user of module '{}'", &e.name))?,
+                    );
+                }
+                for (path, aliases) in &e.aliases {
+                    module.add_aliases(path, aliases, ctx)?;
                 }
             }
         }
@@ -81,96 +187,1527 @@ impl Profile {
             modules,
             active_conf: active,
             required_conf: required,
+            prefer: None,
         };
         Ok(s)
     }
 
-    /// creates new symlinks for any entry that does not have a symlink
-    pub fn sync(&self, force: bool, ctx: &Ctx) -> Result<()> {
-        for name in self
-            .active_conf
+    /// the active profile (or the one named required, that is being switched to)
+    pub fn load(required: Option<&str>, ctx: &Ctx) -> Result<Self> {
+        let active = match (fs::read_to_string(&ctx.profile_file), required) {
+            (Ok(active), _) => toml::from_str::<ProfileDesc>(&active)?,
+            (Err(_), Some(name)) => ProfileDesc {
+                name: name.to_owned(),
+                modules: Default::default(),
+                absent: Default::default(),
+            },
+            (Err(_), None) => return Err(anyhow!("Set a profile with switch-profile.")),
+        };
+        let name = required.unwrap_or(&active.name);
+        let Some(required) = ctx.conf.profiles.iter().find(|p| p.name == name) else {
+            return Err(Error::ProfileNotFound(name.to_owned()).into());
+        };
+        let required = required.clone();
+        Self::new(active, required, ctx)
+    }
+
+    /// true if the module has an entry for the path
+    pub fn tracks(&self, module: &str, path: &str, ctx: &Ctx) -> bool {
+        let Some(m) = self.modules.get(module) else {
+            return false;
+        };
+        m.entry(path, ctx).map(|e| m.contains(&e)).unwrap_or(false)
+    }
+
+    /// makes the module win every conflict for this run
+    pub fn prefer(&mut self, name: String) -> Result<()> {
+        if !self.required_conf.modules.contains(&name) {
+            return Err(anyhow!(
+                "module '{}' is not in profile '{}'",
+                name,
+                &self.required_conf.name
+            ));
+        }
+        self.prefer = Some(name);
+        Ok(())
+    }
+
+    /// the modules, highest precedence first
+    fn by_precedence<'a>(&'a self, modules: &'a [String]) -> impl Iterator<Item = &'a String> {
+        let prefer = self.prefer.iter().filter(|p| modules.contains(p));
+        prefer.chain(
+            modules
+                .iter()
+                .rev()
+                .filter(|m| self.prefer.as_ref() != Some(m)),
+        )
+    }
+
+    /// active modules that are not required
+    fn deactivated(&self) -> Vec<&String> {
+        let required = self.required_conf.modules.iter().collect::<HashSet<_>>();
+        self.active_conf
             .modules
             .iter()
-            .collect::<HashSet<_>>()
-            .difference(&self.required_conf.modules.iter().collect::<HashSet<_>>())
-        {
+            .filter(|m| !required.contains(m))
+            .collect()
+    }
+
+    /// warns if the modules of the active profile were changed in the config since the
+    /// last sync (the next sync applies the config). reordering changes the precedence
+    pub fn warn_changed_modules(&self) {
+        let (was, now) = (&self.active_conf.modules, &self.required_conf.modules);
+        if self.required_conf.name != self.active_conf.name || was == now {
+            return;
+        }
+
+        let added = now.iter().filter(|m| !was.contains(m)).collect::<Vec<_>>();
+        let removed = was.iter().filter(|m| !now.contains(m)).collect::<Vec<_>>();
+        let mut msg = if added.is_empty() && removed.is_empty() {
+            format!(
+                "the order of the modules of profile '{}' changed in the config. sync applies the new precedence (last wins)",
+                &self.required_conf.name
+            )
+        } else {
+            format!(
+                "the modules of profile '{}' changed in the config. sync applies them",
+                &self.required_conf.name
+            )
+        };
+        msg.push_str(&format!("\n  linked: {}", was.join(", ")));
+        msg.push_str(&format!("\n  config: {}", now.join(", ")));
+        if !added.is_empty() {
+            let added = added.iter().map(|m| m.as_str()).collect::<Vec<_>>();
+            msg.push_str(&format!("\n  added:   {}", added.join(", ")));
+        }
+        if !removed.is_empty() {
+            let removed = removed.iter().map(|m| m.as_str()).collect::<Vec<_>>();
+            msg.push_str(&format!("\n  removed: {}", removed.join(", ")));
+        }
+        report::warning(msg);
+    }
+
+    pub fn switch_plan(&self) -> SwitchPlan {
+        let change = |name: &String| {
+            let m = self.modules.get(name).expect("checked in Profile::new");
+            ModuleChange {
+                module: name.clone(),
+                entries: m.home_entries.len() + m.non_home_entries.len(),
+            }
+        };
+
+        SwitchPlan {
+            from: self.active_conf.name.clone(),
+            to: self.required_conf.name.clone(),
+            unlink: self.deactivated().into_iter().map(change).collect(),
+            link: self
+                .required_conf
+                .modules
+                .iter()
+                .filter(|m| !self.active_conf.modules.contains(m))
+                .map(change)
+                .collect(),
+        }
+    }
+
+    pub fn print_plan(&self, ctx: &Ctx) -> Result<()> {
+        let plan = self.switch_plan();
+        if ctx.output == Output::Json {
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+            return Ok(());
+        }
+
+        println!("switching profile: {} -> {}", &plan.from, &plan.to);
+        let width = plan
+            .unlink
+            .iter()
+            .chain(plan.link.iter())
+            .map(|c| c.module.len())
+            .max()
+            .unwrap_or(0);
+        for (kind, title, changes) in [
+            (Kind::Dumped, "unlink", &plan.unlink),
+            (Kind::Created, "link", &plan.link),
+        ] {
+            for c in changes {
+                println!(
+                    "  {} {:<width$} ({} entries)",
+                    report::paint(kind, format!("{:<6}", title)),
+                    &c.module,
+                    c.entries,
+                );
+            }
+        }
+        if plan.unlink.is_empty() && plan.link.is_empty() {
+            println!("  {}", report::paint(Kind::Skipped, "no module changes"));
+        }
+        println!();
+        Ok(())
+    }
+
+    /// creates new symlinks for any entry that does not have a symlink. with keep_going,
+    /// entries that fail are reported at the end instead of stopping the sync
+    pub fn sync(
+        &self,
+        if_exists: IfExists,
+        restore_dumped: bool,
+        keep_going: bool,
+        ctx: &Ctx,
+    ) -> Result<()> {
+        self.check_owners(ctx)?;
+        self.fix_permissions(ctx)?;
+        let actions = self.plan(if_exists, restore_dumped, ctx)?;
+        self.apply(actions, keep_going, ctx)?;
+
+        let prof = toml::to_string_pretty(&self.required_conf)?;
+        fs::write(&ctx.profile_file, prof)?;
+        Snapshot::take(self, ctx).save(ctx)?;
+        self.warn_untracked(ctx)?;
+        Ok(())
+    }
+
+    /// every change that sync makes, in the order it makes them. nothing is changed
+    pub fn plan(
+        &self,
+        if_exists: IfExists,
+        restore_dumped: bool,
+        ctx: &Ctx,
+    ) -> Result<Vec<SyncAction>> {
+        let _timer = stats::timer(|| "plan sync".to_owned());
+        let mut actions = Vec::new();
+        for name in self.deactivated() {
             let module = self.modules.get(name.as_str()).expect("checked earlier");
-            module.unlink_all(force, ctx)?;
+            let restore_from = restore_dumped.then_some(self.active_conf.name.as_str());
+            // entries that are not linked are only an error if conflicts are
+            let ignore_non_links = if_exists != IfExists::Error;
+            actions.extend(module.unlink_actions(ignore_non_links, restore_from, ctx)?);
         }
 
+        let absent = self
+            .required_conf
+            .absent
+            .iter()
+            .map(|p| self.absent_entry(p, ctx))
+            .collect::<Result<Vec<_>>>()?;
+
         let mut synced = HashSet::new();
-        for name in self.required_conf.modules.iter().rev() {
+        for name in self.by_precedence(&self.required_conf.modules) {
             let module = self.modules.get(name).expect("checked in Profile::new");
+            if let Some(reason) = &module.unmet {
+                actions.push(SyncAction::SkipModule {
+                    module: name.clone(),
+                    entries: module.entries(ctx).count(),
+                    reason: reason.clone(),
+                });
+                actions.extend(module.unlink_actions(true, None, ctx)?);
+                continue;
+            }
+            let hashes = match module.mode {
+                LinkMode::Symlink => None,
+                LinkMode::Copy => Some(HashState::load(ctx, name)?),
+            };
 
-            for e in module
-                .home_entries
+            for e in module.entries(ctx) {
+                // ignore if already synced by a module with higher precedence
+                if synced.contains(&e.src) {
+                    continue;
+                }
+                if absent.iter().any(|a| a.src == e.src) {
+                    return Err(anyhow!(
+                        "path {:?} is tracked by module '{}' and also marked absent",
+                        &e.src,
+                        name
+                    ));
+                }
+                synced.insert(e.src.clone());
+                actions.extend(Self::entry_action(e, if_exists, hashes.as_ref())?);
+            }
+        }
+
+        for e in absent {
+            if e.src.exists() || e.src.is_symlink() {
+                actions.push(SyncAction::RemoveAbsent { entry: e });
+            }
+        }
+        Ok(actions)
+    }
+
+    /// like plan, but only for the entries with files in the repo that changed between rev and
+    /// HEAD. the links of deleted entries are deleted. errors if git can not tell what
+    /// changed or if the modules of the profile changed (a full sync is needed then)
+    pub fn plan_since(&self, rev: &str, if_exists: IfExists, ctx: &Ctx) -> Result<Vec<SyncAction>> {
+        let _timer = stats::timer(|| "plan sync (since commit)".to_owned());
+        if self.active_conf.modules != self.required_conf.modules {
+            return Err(anyhow!(
+                "the modules of profile '{}' changed since the last sync",
+                &self.required_conf.name
+            ));
+        }
+        let changed = ctx
+            .changed_since(rev)
+            .with_context(|| format!("could not find what changed since '{}'", rev))?;
+
+        let mut actions = Vec::new();
+        let mut entries = HashSet::new();
+        for path in changed.iter() {
+            let Some(m) = self
+                .required_conf
+                .modules
+                .iter()
+                .map(|m| self.modules.get(m).expect("checked in Profile::new"))
+                .find(|m| path.starts_with(&m.module_dir))
+            else {
+                continue;
+            };
+            // a file inside a dir that is linked as a whole belongs to the dir
+            let tracked = path
+                .ancestors()
+                .take_while(|p| *p != m.module_dir)
+                .filter_map(|p| m.entry_from_dest(p, ctx).ok())
+                .find(|e| m.contains(e));
+            match tracked {
+                Some(e) => {
+                    entries.insert(e.dest);
+                }
+                None => {
+                    // deleted from the repo. the link into it is left broken
+                    let Ok(e) = m.entry_from_dest(path, ctx) else {
+                        continue;
+                    };
+                    let broken =
+                        !path.exists() && fs::read_link(&e.src).ok().as_ref() == Some(&e.dest);
+                    if broken {
+                        actions.push(SyncAction::DeleteBroken { entry: e });
+                    }
+                }
+            }
+        }
+
+        let mut synced = HashSet::new();
+        for name in self.by_precedence(&self.required_conf.modules) {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            if module.unmet.is_some() {
+                continue;
+            }
+            let hashes = match module.mode {
+                LinkMode::Symlink => None,
+                LinkMode::Copy => Some(HashState::load(ctx, name)?),
+            };
+            for e in module.entries(ctx) {
+                if !synced.insert(e.src.clone()) {
+                    continue;
+                }
+                // aliases share the dest of their entry
+                if !entries.contains(&e.dest) {
+                    continue;
+                }
+                actions.extend(Self::entry_action(e, if_exists, hashes.as_ref())?);
+            }
+        }
+        Ok(actions)
+    }
+
+    /// what syncing a single entry does (nothing if it is already in place). hashes are the
+    /// recorded hashes of the copies of the module (None for symlinked modules)
+    fn entry_action(
+        e: Entry,
+        if_exists: IfExists,
+        hashes: Option<&HashState>,
+    ) -> Result<Option<SyncAction>> {
+        let Some(hashes) = hashes else {
+            return Ok(match e.state()? {
+                EntryState::Missing => Some(SyncAction::Symlink {
+                    entry: e,
+                    dump: false,
+                }),
+                EntryState::Linked => None,
+                // nothing is lost by replacing an identical copy
+                EntryState::Unsynced => Some(SyncAction::Symlink {
+                    entry: e,
+                    dump: true,
+                }),
+                EntryState::Conflict => match if_exists {
+                    IfExists::Error => Some(SyncAction::Conflict { entry: e }),
+                    IfExists::Skip => Some(SyncAction::KeepExisting { entry: e }),
+                    IfExists::Overwrite => Some(SyncAction::Symlink {
+                        entry: e,
+                        dump: true,
+                    }),
+                },
+            });
+        };
+
+        // the hash of the last copy is recorded so that the copy is only rewritten if it
+        // was not edited since
+        if !(e.src.exists() || e.src.is_symlink()) {
+            return Ok(Some(SyncAction::Copy {
+                entry: e,
+                dump: false,
+            }));
+        }
+        let repo_hash = hash_path(&e.dest)?;
+        let live_hash = (!e.src.is_symlink())
+            .then(|| hash_path(&e.src))
+            .transpose()?;
+        let recorded = hashes.hashes.get(&e.src);
+        if live_hash.as_ref() == Some(&repo_hash) {
+            if recorded == Some(&repo_hash) {
+                return Ok(None);
+            }
+            return Ok(Some(SyncAction::RecordHash { entry: e }));
+        }
+        if live_hash.is_some() && live_hash.as_ref() == recorded {
+            // only the repo changed since the last sync
+            return Ok(Some(SyncAction::UpdateCopy { entry: e }));
+        }
+        if if_exists == IfExists::Overwrite {
+            return Ok(Some(SyncAction::Copy {
+                entry: e,
+                dump: true,
+            }));
+        }
+        if recorded.is_some() {
+            return Ok(Some(SyncAction::KeepEdited { entry: e }));
+        }
+        match if_exists {
+            IfExists::Skip => Ok(Some(SyncAction::KeepExisting { entry: e })),
+            _ => Ok(Some(SyncAction::Conflict { entry: e })),
+        }
+    }
+
+    /// runs the actions of a plan in order. with keep_going, entries that fail are reported
+    /// at the end instead of stopping
+    pub fn apply(&self, actions: Vec<SyncAction>, keep_going: bool, ctx: &Ctx) -> Result<()> {
+        let _timer = stats::timer(|| "apply sync".to_owned());
+        let mut hashes = HashMap::new();
+        let mut failed = Vec::new();
+        let mut left = Vec::new();
+        let mut run =
+            || -> Result<()> {
+                for action in actions.iter() {
+                    match self.run_action(action, &mut hashes, ctx) {
+                        Err(err) if keep_going => {
+                            let src = action.entry().map(|e| e.src.as_path());
+                            failed.push(err.context(format!(
+                                "could not sync {:?}",
+                                src.unwrap_or(Path::new(""))
+                            )));
+                        }
+                        res => res?,
+                    }
+                    if let SyncAction::Leave { entry } = action {
+                        left.push(entry.src.clone());
+                    }
+                }
+                Ok(())
+            };
+        let res = run();
+        // the copies that were made are recorded even if the sync stopped half way
+        for (name, state) in hashes.iter() {
+            state.save(ctx, name)?;
+        }
+        res?;
+
+        if !left.is_empty() {
+            report::warning(format!(
+                "left {} entries of deactivated modules that were not linked: {:?}",
+                left.len(),
+                &left
+            ));
+        }
+
+        if !failed.is_empty() {
+            for err in &failed {
+                report::error(err);
+            }
+            return Err(anyhow!(
+                "{} entries failed to sync. the active profile was not updated",
+                failed.len()
+            ));
+        }
+        Ok(())
+    }
+
+    fn run_action(
+        &self,
+        action: &SyncAction,
+        hashes: &mut HashMap<String, HashState>,
+        ctx: &Ctx,
+    ) -> Result<()> {
+        let mut record = |e: &Entry| -> Result<()> {
+            let module = e.module.as_ref().expect("entry of a module");
+            if !hashes.contains_key(module) {
+                hashes.insert(module.clone(), HashState::load(ctx, module)?);
+            }
+            let state = hashes.get_mut(module).expect("inserted above");
+            state.hashes.insert(e.src.clone(), hash_path(&e.dest)?);
+            Ok(())
+        };
+
+        match action {
+            SyncAction::Unlink { entry: e, restore } => {
+                let title = match e.mode {
+                    LinkMode::Symlink => "deleting symlink",
+                    LinkMode::Copy => "deleting copy",
+                };
+                report::action(Kind::Dumped, title, &[("src", &e.src)]);
+                e.rm_src_file(ctx)?;
+                if let Some(dump) = restore {
+                    report::action(
+                        Kind::Created,
+                        "restoring dumped path",
+                        &[("src", &e.src), ("dump", dump)],
+                    );
+                    e.restore_dump(dump, ctx)?;
+                }
+            }
+            SyncAction::DeleteBroken { entry: e } => {
+                report::action(Kind::Dumped, "deleting broken symlink", &[("src", &e.src)]);
+                e.rm_src_file(ctx)?;
+            }
+            SyncAction::Leave { entry: e } => {
+                report::skipped(format!("not linked to the repo. leaving {:?}", &e.src));
+            }
+            SyncAction::SkipModule {
+                module,
+                entries,
+                reason,
+            } => {
+                report::skipped(format!(
+                    "skipping {} entries of module '{}': {}",
+                    entries, module, reason
+                ));
+            }
+            SyncAction::Symlink { entry: e, dump } => {
+                self.create_parents(e, ctx)?;
+                if *dump {
+                    report::action(
+                        Kind::Dumped,
+                        "moving contents to dump",
+                        &[("src", &e.src), ("dump", ctx.dump_target()?)],
+                    );
+                    e.dump(ctx)?;
+                }
+                report::action(
+                    Kind::Created,
+                    "creating symlink",
+                    &[("src", &e.src), ("dst", &e.dest)],
+                );
+                e.symlink_to_src(ctx)?;
+                self.give_to_owner(e, &e.src, ctx)?;
+            }
+            SyncAction::Copy { entry: e, dump } => {
+                self.create_parents(e, ctx)?;
+                if *dump {
+                    report::action(
+                        Kind::Dumped,
+                        "moving contents to dump",
+                        &[("src", &e.src), ("dump", ctx.dump_target()?)],
+                    );
+                    e.dump(ctx)?;
+                }
+                report::action(
+                    Kind::Created,
+                    "copying path",
+                    &[("src", &e.src), ("dst", &e.dest)],
+                );
+                e.materialize(ctx)?;
+                record(e)?;
+                self.give_to_owner(e, &e.src, ctx)?;
+            }
+            SyncAction::UpdateCopy { entry: e } => {
+                self.check_parents(e, ctx)?;
+                report::action(
+                    Kind::Created,
+                    "updating copy",
+                    &[("src", &e.src), ("dst", &e.dest)],
+                );
+                e.rm_src_file(ctx)?;
+                e.materialize(ctx)?;
+                record(e)?;
+                self.give_to_owner(e, &e.src, ctx)?;
+            }
+            SyncAction::RecordHash { entry: e } => record(e)?,
+            SyncAction::KeepEdited { entry: e } => {
+                report::warning(format!(
+                    "{:?} was edited since it was last synced. use --if-exists overwrite to overwrite it",
+                    &e.src
+                ));
+            }
+            SyncAction::Conflict { entry: e } => {
+                return Err(Error::ConflictNeedsForce(e.src.clone()).into());
+            }
+            SyncAction::KeepExisting { entry: e } => {
+                report::skipped(format!("something is already at {:?}. leaving it", &e.src));
+            }
+            SyncAction::RemoveAbsent { entry: e } => {
+                report::action(
+                    Kind::Dumped,
+                    "removing absent path",
+                    &[("src", &e.src), ("dump", ctx.dump_target()?)],
+                );
+                e.dump(ctx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// entries of the required profile in the repo that are not committed to git. empty
+    /// unless check_git_tracked is set
+    pub fn untracked(&self, ctx: &Ctx) -> Result<Vec<PathBuf>> {
+        if !ctx.conf.check_git_tracked {
+            return Ok(Vec::new());
+        }
+        let Some(tracked) = ctx.git_tracked()? else {
+            return Ok(Vec::new());
+        };
+
+        let mut untracked = Vec::new();
+        let mut seen = HashSet::new();
+        for name in self.required_conf.modules.iter() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            for e in module.entries(ctx).filter(|e| seen.insert(e.dest.clone())) {
+                // modules with a custom path are not in the repo
+                let Ok(rel) = e.dest.strip_prefix(&ctx.canon_repo) else {
+                    continue;
+                };
+                // a dir counts as tracked if anything in it is
+                let found = tracked
+                    .range(rel.to_path_buf()..)
+                    .next()
+                    .map(|t| t.starts_with(rel))
+                    .unwrap_or(false);
+                if !found {
+                    untracked.push(e.dest);
+                }
+            }
+        }
+        Ok(untracked)
+    }
+
+    fn warn_untracked(&self, ctx: &Ctx) -> Result<()> {
+        let untracked = self.untracked(ctx)?;
+        if !untracked.is_empty() {
+            report::warning(format!(
+                "{} entries are not committed to git: {:?}",
+                untracked.len(),
+                &untracked
+            ));
+        }
+        Ok(())
+    }
+
+    /// counts the modules and entries of the required profile and the bytes in the repo
+    /// that they take
+    pub fn info(&self, ctx: &Ctx) -> Result<()> {
+        let mut entries = 0;
+        let mut size = 0;
+        let mut seen = HashSet::new();
+        for name in self.required_conf.modules.iter() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            // aliases share the dest of their entry
+            for e in module.entries(ctx).filter(|e| seen.insert(e.dest.clone())) {
+                entries += 1;
+                if e.dest.exists() || e.dest.is_symlink() {
+                    size += path_size(&e.dest)?;
+                }
+            }
+        }
+
+        let modules = self.required_conf.modules.len();
+        if ctx.output == Output::Json {
+            let out = serde_json::json!({
+                "profile": &self.required_conf.name,
+                "modules": modules,
+                "entries": entries,
+                "bytes": size,
+            });
+            println!("{}", serde_json::to_string_pretty(&out)?);
+            return Ok(());
+        }
+        println!("profile: {}", &self.required_conf.name);
+        println!("  modules: {}", modules);
+        println!("  entries: {}", entries);
+        println!("  size:    {}", human_size(size));
+        Ok(())
+    }
+
+    /// lists every path that a sync without -f would stop at. nothing is changed
+    pub fn check(&self, ctx: &Ctx) -> Result<()> {
+        let mut conflicts = Vec::new();
+        for name in self.deactivated() {
+            let module = self.modules.get(name.as_str()).expect("checked earlier");
+            for e in module.entries(ctx) {
+                if !matches!(e.state()?, EntryState::Linked | EntryState::Missing) {
+                    conflicts.push((e.src, name.as_str()));
+                }
+            }
+        }
+
+        let mut synced = HashSet::new();
+        for name in self.by_precedence(&self.required_conf.modules) {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            if module.unmet.is_some() {
+                continue;
+            }
+            let hashes = match module.mode {
+                LinkMode::Symlink => None,
+                LinkMode::Copy => Some(HashState::load(ctx, name)?),
+            };
+            for e in module.entries(ctx) {
+                if !synced.insert(e.src.clone()) {
+                    continue;
+                }
+                let in_the_way = match &hashes {
+                    None => e.state()? == EntryState::Conflict,
+                    // copies edited since the last sync are skipped with a warning
+                    Some(hashes) => {
+                        !matches!(e.state()?, EntryState::Linked | EntryState::Missing)
+                            && !hashes.hashes.contains_key(&e.src)
+                    }
+                };
+                if in_the_way {
+                    conflicts.push((e.src, name.as_str()));
+                }
+            }
+        }
+
+        if ctx.output == Output::Json {
+            let out = conflicts
                 .iter()
-                .map(|p| module.entry_from_relative(&RelativePath::Home(p.to_path_buf()), ctx))
-                .chain(module.non_home_entries.iter().map(|p| {
-                    module.entry_from_relative(&RelativePath::NonHome(p.to_path_buf()), ctx)
-                }))
+                .map(|(src, module)| serde_json::json!({ "src": src, "module": module }))
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::to_string_pretty(&out)?);
+        } else {
+            for (src, module) in conflicts.iter() {
+                println!(
+                    "  {} {:?} ({})",
+                    report::paint(Kind::Warning, format!("{:<8}", "conflict")),
+                    src,
+                    module
+                );
+            }
+        }
+        match conflicts.len() {
+            0 => {
+                if ctx.output != Output::Json {
+                    report::skipped("nothing is in the way");
+                }
+                Ok(())
+            }
+            n => Err(anyhow!(
+                "{} paths are in the way of the sync. use --if-exists overwrite (or skip) to sync anyway",
+                n
+            )),
+        }
+    }
+
+    /// makes the required profile the active one without linking anything. the modules
+    /// that are linked stay in the profile file, so the next sync switches away from them
+    pub fn select(&self, ctx: &Ctx) -> Result<()> {
+        let marker = ProfileDesc {
+            name: self.required_conf.name.clone(),
+            ..self.active_conf.clone()
+        };
+        fs::write(&ctx.profile_file, toml::to_string_pretty(&marker)?)?;
+        report::skipped(format!(
+            "switched to profile '{}'. run sync to apply it",
+            &marker.name
+        ));
+        Ok(())
+    }
+
+    /// only creates the missing links/copies of the active modules. nothing is ever dumped
+    /// or overwritten, so this errors before changing anything if any entry is in the way
+    pub fn link_only(&self, ctx: &Ctx) -> Result<()> {
+        if self.active_conf.modules != self.required_conf.modules {
+            return Err(anyhow!(
+                "the modules of profile '{}' changed since the last sync. run a full sync instead",
+                &self.required_conf.name
+            ));
+        }
+        self.check_owners(ctx)?;
+
+        let mut synced = HashSet::new();
+        let mut missing = Vec::new();
+        let mut in_the_way = Vec::new();
+        for name in self.required_conf.modules.iter().rev() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            if module.unmet.is_some() {
+                continue;
+            }
+            for e in module.entries(ctx) {
+                if !synced.insert(e.src.clone()) {
+                    continue;
+                }
+                match e.state()? {
+                    EntryState::Linked => (),
+                    EntryState::Missing => missing.push(e),
+                    EntryState::Unsynced | EntryState::Conflict => in_the_way.push(e.src),
+                }
+            }
+        }
+
+        if !in_the_way.is_empty() {
+            return Err(anyhow!(
+                "there are already files/dirs at: {:?}. run sync without --link-only to resolve them",
+                &in_the_way
+            ));
+        }
+
+        for e in missing {
+            self.create_parents(&e, ctx)?;
+
+            match e.mode {
+                LinkMode::Symlink => {
+                    report::action(
+                        Kind::Created,
+                        "creating symlink",
+                        &[("src", &e.src), ("dst", &e.dest)],
+                    );
+                    e.symlink_to_src(ctx)?;
+                }
+                LinkMode::Copy => {
+                    let module = e.module.as_ref().expect("entry of a module");
+                    report::action(
+                        Kind::Created,
+                        "copying path",
+                        &[("src", &e.src), ("dst", &e.dest)],
+                    );
+                    e.materialize(ctx)?;
+
+                    let mut hashes = HashState::load(ctx, module)?;
+                    hashes.hashes.insert(e.src.clone(), hash_path(&e.dest)?);
+                    hashes.save(ctx, module)?;
+                }
+            }
+            self.give_to_owner(&e, &e.src, ctx)?;
+        }
+        Ok(())
+    }
+
+    /// re-points the symlinks of the linked modules that still point into the old location
+    /// of the repo. if old_repo is not known, any link ending in the path of the entry in
+    /// the repo is taken to be one
+    pub fn relink(&self, old_repo: Option<&Path>, ctx: &Ctx) -> Result<()> {
+        let mut synced = HashSet::new();
+        let mut relinked = 0;
+        for name in self.active_conf.modules.iter().rev() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            if module.mode != LinkMode::Symlink {
+                continue;
+            }
+            for e in module.entries(ctx) {
+                if !synced.insert(e.src.clone()) {
+                    continue;
+                }
+                let Ok(target) = fs::read_link(&e.src) else {
+                    continue;
+                };
+                if target == e.dest {
+                    continue;
+                }
+                // only entries in the repo move with it
+                let Ok(in_repo) = e.dest.strip_prefix(&ctx.canon_repo) else {
+                    continue;
+                };
+                let moved = match old_repo {
+                    Some(old) => target.strip_prefix(old).ok() == Some(in_repo),
+                    None => target.ends_with(in_repo),
+                };
+                if !moved {
+                    continue;
+                }
+
+                report::action(
+                    Kind::Created,
+                    "relinking",
+                    &[("src", &e.src), ("was", &target), ("dst", &e.dest)],
+                );
+                e.with_priv(ctx, || Ok(fs::remove_file(&e.src)?))?;
+                e.symlink_to_src(ctx)?;
+                relinked += 1;
+            }
+        }
+        match relinked {
+            0 => report::skipped("nothing to relink"),
+            n => report::skipped(format!("relinked {} entries", n)),
+        }
+        Ok(())
+    }
+
+    /// deletes the symlinks of module (or of every active module) that point into the repo.
+    /// nothing else is touched, so a sync links them again
+    pub fn unlink(&self, module: Option<&str>, ctx: &Ctx) -> Result<()> {
+        let names = match module {
+            Some(name) => {
+                if !self.active_conf.modules.iter().any(|n| n == name) {
+                    return Err(Error::ModuleNotActive(name.to_owned()).into());
+                }
+                let m = self.modules.get(name).expect("checked in Profile::new");
+                if m.mode != LinkMode::Symlink {
+                    return Err(anyhow!(
+                        "module '{}' is copied, not linked. there is nothing to unlink",
+                        name
+                    ));
+                }
+                vec![name.to_owned()]
+            }
+            None => self.active_conf.modules.clone(),
+        };
+
+        let mut unlinked = 0;
+        for name in names.iter() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            if module.mode != LinkMode::Symlink {
+                report::skipped(format!(
+                    "module '{}' is copied. leaving the copies as they are",
+                    name
+                ));
+                continue;
+            }
+            for e in module.entries(ctx) {
+                // links of other modules (and whatever is not ours) stay
+                if e.state()? != EntryState::Linked {
+                    continue;
+                }
+                report::action(
+                    Kind::Dumped,
+                    format!("unlinking from module '{}'", name),
+                    &[("src", &e.src)],
+                );
+                e.rm_src_file(ctx)?;
+                unlinked += 1;
+            }
+        }
+        match unlinked {
+            0 => report::skipped("nothing to unlink"),
+            n => report::skipped(format!("unlinked {} entries. sync to link them again", n)),
+        }
+        Ok(())
+    }
+
+    /// takes over links into the module that were made by hand (or by another tool) as they
+    /// are. nothing is moved or linked, dirs that are linked as a whole only get a STUB.
+    /// whatever looks managed but is not a clean link is reported
+    pub fn adopt(&self, name: &str, ctx: &Ctx) -> Result<()> {
+        // the module is usually added to the profile right before (and not synced yet)
+        if !self.required_conf.modules.iter().any(|n| n == name) {
+            return Err(anyhow!(
+                "module '{}' is not in profile '{}'",
+                name,
+                &self.required_conf.name
+            ));
+        }
+        let module = self.modules.get(name).expect("checked in Profile::new");
+        if module.read_only {
+            return Err(Error::ModuleReadOnly(name.to_owned()).into());
+        }
+        if module.mode != LinkMode::Symlink {
+            return Err(anyhow!(
+                "module '{}' is copied, not linked. there are no links to adopt",
+                name
+            ));
+        }
+
+        let stubbed = module.missing_stubs(ctx);
+        for dir in stubbed.iter() {
+            report::action(Kind::Created, "adopting linked dir", &[("dir", dir)]);
+            let _ = fs::File::create(dir.join(STUB))?;
+        }
+
+        let shadowed = self.shadowed(ctx);
+        let mut linked = stubbed.len();
+        let mut unclean = 0;
+        for e in module.entries(ctx) {
+            // files of the dirs that just got a stub are not entries anymore
+            if stubbed.iter().any(|d| e.dest.starts_with(d))
+                || shadowed.contains_key(&(name.to_owned(), e.src.clone()))
             {
-                let src = e.src.clone();
-                // ignore if already synced by a module with higher precedence
-                if synced.contains(&src) {
+                continue;
+            }
+            let why = match e.state()? {
+                EntryState::Linked => {
+                    linked += 1;
                     continue;
                 }
-                synced.insert(src);
+                EntryState::Missing => "not linked",
+                EntryState::Unsynced => "a copy of the entry, not a link to it",
+                _ if e.src.is_symlink() => "links somewhere else",
+                _ => "something else is in the way",
+            };
+            unclean += 1;
+            report::action(Kind::Warning, why, &[("src", &e.src), ("dst", &e.dest)]);
+        }
 
-                self.sync_entry(&e, force, ctx)?;
+        // links into the module that do not match where it keeps the path
+        let mut pending = vec![(module.home_dir(ctx).to_path_buf(), 0)];
+        while let Some((dir, depth)) = pending.pop() {
+            let Ok(read) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for p in read.filter_map(|e| e.ok()).map(|e| e.path()) {
+                if p.starts_with(&ctx.canon_repo) {
+                    continue;
+                }
+                if !p.is_symlink() {
+                    if p.is_dir() && depth < ADOPT_SCAN_DEPTH {
+                        pending.push((p, depth + 1));
+                    }
+                    continue;
+                }
+                let Ok(target) = canonicalize(&p) else {
+                    continue;
+                };
+                if !target.starts_with(&module.module_dir) {
+                    continue;
+                }
+                let Ok(e) = module.entry_from_src(&p, ctx) else {
+                    continue;
+                };
+                if e.dest != target && !module.contains(&e) {
+                    unclean += 1;
+                    report::action(
+                        Kind::Warning,
+                        "links into the module, but not where the module keeps it",
+                        &[("src", &p), ("links to", &target), ("expected", &e.dest)],
+                    );
+                }
             }
         }
 
-        let prof = toml::to_string_pretty(&self.required_conf)?;
-        fs::write(&ctx.profile_file, prof)?;
+        report::skipped(format!(
+            "{} entries of module '{}' are linked",
+            linked, name
+        ));
+        if unclean > 0 {
+            report::warning(format!(
+                "{} paths need fixing by hand (or sync -f to link the entries)",
+                unclean
+            ));
+        }
+        Ok(())
+    }
+
+    /// see Config::fix_permissions
+    fn fix_permissions(&self, ctx: &Ctx) -> Result<()> {
+        if !ctx.conf.fix_permissions {
+            return Ok(());
+        }
+        let mut fixed = 0;
+        for name in self.required_conf.modules.iter() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            // the clones of git modules are not ours to change
+            if module.read_only {
+                continue;
+            }
+            for e in module.entries(ctx) {
+                if e.dest.exists() {
+                    fixed += fix_permissions(&e.dest, ctx)?.len();
+                }
+            }
+        }
+        if fixed > 0 {
+            report::skipped(format!(
+                "fixed the permissions of {} paths in the repo",
+                fixed
+            ));
+        }
+        Ok(())
+    }
+
+    /// creates the parent dirs of src. the ones created for a module with a user are given
+    /// to that user
+    fn create_parents(&self, e: &Entry, ctx: &Ctx) -> Result<()> {
+        self.check_parents(e, ctx)?;
+        let parent = e.src.parent().expect("must have a parent");
+        let created = parent
+            .ancestors()
+            .take_while(|p| !p.exists())
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        e.with_priv(ctx, || Ok(fs::create_dir_all(parent)?))?;
+        for dir in created.iter().rev() {
+            self.give_to_owner(e, dir, ctx)?;
+        }
         Ok(())
     }
 
-    fn sync_entry(&self, e: &Entry, force: bool, ctx: &Ctx) -> Result<()> {
-        let privilege = e.get_priv(ctx)?;
-        fs::create_dir_all(e.src.parent().unwrap())?;
-        drop(privilege);
+    /// ancestors of src that are symlinks are followed, so the entry is linked inside the dir
+    /// they point at. refuses ancestors that are broken symlinks, and ones that resolve into
+    /// a module (like a dir that is linked as a whole), as src would then be in the repo
+    fn check_parents(&self, e: &Entry, ctx: &Ctx) -> Result<()> {
+        let parent = e.src.parent().expect("must have a parent");
+        for a in parent.ancestors() {
+            if a.is_symlink() && !a.exists() {
+                return Err(anyhow!(
+                    "can not link {:?}: {:?} is a broken symlink",
+                    &e.src,
+                    a
+                ));
+            }
+            if !a.exists() {
+                continue;
+            }
+            // the deepest existing ancestor resolves every link above it too
+            let canon = canonicalize(a)?;
+            let in_repo = canon.starts_with(&ctx.canon_repo)
+                || self
+                    .modules
+                    .values()
+                    .any(|m| canon.starts_with(&m.module_dir));
+            if in_repo {
+                return Err(anyhow!(
+                    "can not link {:?}: {:?} resolves to {:?}, which is in the repo. it is probably linked as a whole (see status --fix), or is inside the entry of another module",
+                    &e.src,
+                    a,
+                    &canon
+                ));
+            }
+            break;
+        }
+        Ok(())
+    }
+
+    /// gives path to the user of the module of the entry (if it has one)
+    fn give_to_owner(&self, e: &Entry, path: &Path, ctx: &Ctx) -> Result<()> {
+        let Some(owner) = e
+            .module
+            .as_ref()
+            .and_then(|m| self.modules.get(m))
+            .and_then(|m| m.owner.as_ref())
+        else {
+            return Ok(());
+        };
+        if !(path.exists() || path.is_symlink()) {
+            return Ok(());
+        }
+        ctx.with_privileges(|| owner.chown(path))
+    }
+
+    /// modules that link into the home of another user need root
+    fn check_owners(&self, ctx: &Ctx) -> Result<()> {
+        if ctx.root_user.is_some() {
+            return Ok(());
+        }
+        for name in self.required_conf.modules.iter() {
+            let m = self.modules.get(name).expect("checked in Profile::new");
+            if let Some(owner) = &m.owner {
+                return Err(anyhow!(
+                    "module '{}' links into the home of user '{}'. run configma with sudo",
+                    name,
+                    &owner.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// entry for a path that must not exist. it's dest is where it would be dumped to
+    fn absent_entry(&self, path: impl AsRef<str>, ctx: &Ctx) -> Result<Entry> {
+        let path = path.as_ref();
+        let src = PathBuf::from(
+            shellexpand::tilde_with_context(path, || Some(ctx.canon_home_dir.to_string_lossy()))
+                .into_owned(),
+        );
+        if !src.is_absolute() {
+            return Err(anyhow!(
+                "absent path must be absolute or start with '~': {}",
+                path
+            ));
+        }
+
+        let relative = match src.strip_prefix(&ctx.canon_home_dir) {
+            Ok(p) => RelativePath::Home(p.to_path_buf()),
+            Err(_) => RelativePath::NonHome(
+                src.strip_prefix("/")
+                    .expect("checked absolute above")
+                    .to_path_buf(),
+            ),
+        };
+
+        let mut e = Entry {
+            dest: PathBuf::new(),
+            src,
+            relative,
+            mode: LinkMode::Symlink,
+            module: None,
+        };
+        e.dest = ctx.dump_base.join(e.dump_relative(ctx.conf.dump_layout));
+        Ok(e)
+    }
+
+    /// entries of the required modules that are not linked because a module with higher
+    /// precedence has the same src. keyed by (module, src), the value is the winner
+    pub fn shadowed(&self, ctx: &Ctx) -> HashMap<(String, PathBuf), String> {
+        let mut winners = HashMap::new();
+        let mut shadowed = HashMap::new();
+        for name in self.by_precedence(&self.required_conf.modules) {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            if module.unmet.is_some() {
+                continue;
+            }
+            for e in module.entries(ctx) {
+                match winners.get(&e.src) {
+                    Some(winner) if winner != name => {
+                        shadowed.insert((name.clone(), e.src), String::clone(winner));
+                    }
+                    Some(_) => (),
+                    None => {
+                        winners.insert(e.src, name.clone());
+                    }
+                }
+            }
+        }
+        shadowed
+    }
+
+    fn status_json(&self, ctx: &Ctx) -> Result<()> {
+        let shadowed = self.shadowed(ctx);
+        let mut entries = Vec::new();
+        for name in self.required_conf.modules.iter().rev() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            for e in module.entries(ctx) {
+                entries.push(serde_json::json!({
+                    "module": name,
+                    "src": &e.src,
+                    "dest": &e.dest,
+                    "state": e.state()?.name(),
+                    "shadowed_by": shadowed.get(&(name.clone(), e.src.clone())),
+                }));
+            }
+        }
+        let out = serde_json::json!({
+            "profile": &self.required_conf.name,
+            "active": self.required_conf.name == self.active_conf.name,
+            "entries": entries,
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        Ok(())
+    }
+
+    pub fn status(&self, ctx: &Ctx) -> Result<()> {
+        if ctx.output == Output::Json {
+            return self.status_json(ctx);
+        }
+        if self.required_conf.name == self.active_conf.name {
+            println!("profile: {}", &self.required_conf.name);
+            self.warn_changed_modules();
+        } else {
+            println!(
+                "profile: {} (not active. active profile: {})",
+                &self.required_conf.name, &self.active_conf.name
+            );
+        }
+
+        if ctx.output == Output::Table {
+            self.status_table(ctx)?;
+        } else {
+            let shadowed = self.shadowed(ctx);
+            for name in self.required_conf.modules.iter().rev() {
+                let module = self.modules.get(name).expect("checked in Profile::new");
+                println!("\nmodule: {}", name);
+
+                for e in module.entries(ctx) {
+                    let state = e.state()?;
+                    let winner = shadowed.get(&(name.clone(), e.src.clone()));
+                    println!(
+                        "  {} {:?}{}",
+                        report::paint(state.kind(), format!("{:<8}", state.name())),
+                        &e.src,
+                        winner
+                            .map(
+                                |w| report::paint(Kind::Skipped, format!(" (shadowed by {})", w))
+                                    .to_string()
+                            )
+                            .unwrap_or_default()
+                    );
+                }
+            }
+
+            if !self.required_conf.absent.is_empty() {
+                println!("\nabsent:");
+                for path in &self.required_conf.absent {
+                    let e = self.absent_entry(path, ctx)?;
+                    let (kind, state) = if e.src.exists() || e.src.is_symlink() {
+                        (Kind::Warning, "present")
+                    } else {
+                        (Kind::Created, "removed")
+                    };
+                    println!(
+                        "  {} {:?}",
+                        report::paint(kind, format!("{:<8}", state)),
+                        &e.src
+                    );
+                }
+            }
+        }
+
+        let missing = self.missing_stubs(ctx);
+        if !missing.is_empty() {
+            println!("\ndirs linked as a whole without a stub (fix with --fix):");
+            for dir in missing {
+                println!(
+                    "  {} {:?}",
+                    report::paint(Kind::Warning, format!("{:<8}", "no stub")),
+                    dir
+                );
+            }
+        }
+
+        let orphans = self.orphans(ctx);
+        if !orphans.is_empty() {
+            println!("\norphaned modules (not in any profile):");
+            for m in orphans {
+                println!(
+                    "  {} {:?}",
+                    report::paint(Kind::Warning, format!("{:<8}", &m.name)),
+                    &m.module_dir
+                );
+            }
+        }
+
+        let untracked = self.untracked(ctx)?;
+        if !untracked.is_empty() {
+            println!("\nentries not committed to git:");
+            for dest in untracked {
+                println!(
+                    "  {} {:?}",
+                    report::paint(Kind::Warning, format!("{:<8}", "new")),
+                    dest
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// dirs of the required modules that should be marked with a STUB but are not
+    pub fn missing_stubs(&self, ctx: &Ctx) -> Vec<PathBuf> {
+        self.required_conf
+            .modules
+            .iter()
+            .map(|name| self.modules.get(name).expect("checked in Profile::new"))
+            .filter(|m| !m.read_only)
+            .flat_map(|m| m.missing_stubs(ctx))
+            .collect()
+    }
+
+    /// finds the dirs that lost their STUB (e.g. in a merge) while they are still linked as
+    /// a whole, and STUB files that git ignores (and so would not be committed). with fix,
+    /// the missing STUB files are recreated
+    pub fn doctor(&self, fix: bool, ctx: &Ctx) -> Result<()> {
+        let missing = self.missing_stubs(ctx);
+        let mut problems = 0;
+        if missing.is_empty() {
+            report::skipped("every dir that is linked as a whole has a stub");
+        } else if fix {
+            self.fix_stubs(ctx)?;
+        } else {
+            problems += missing.len();
+            println!("dirs linked as a whole without a stub (fix with --fix):");
+            for dir in missing.iter() {
+                println!(
+                    "  {} {:?}",
+                    report::paint(Kind::Warning, format!("{:<8}", "no stub")),
+                    dir
+                );
+            }
+            println!();
+        }
+
+        let stubs = self
+            .required_conf
+            .modules
+            .iter()
+            .map(|name| self.modules.get(name).expect("checked in Profile::new"))
+            .flat_map(|m| m.entries(ctx))
+            .filter(|e| e.dest.join(STUB).exists())
+            .map(|e| e.dest.join(STUB))
+            .chain(missing.iter().map(|d| d.join(STUB)))
+            .collect::<Vec<_>>();
+        match ctx.git_ignored(&stubs)? {
+            None => report::skipped("the repo is not a git repo. not checking ignored stubs"),
+            Some(ignored) if ignored.is_empty() => {
+                report::skipped("git does not ignore any stub");
+            }
+            Some(ignored) => {
+                problems += ignored.len();
+                println!(
+                    "stubs ignored by git (they are lost on other machines. do not ignore {}):",
+                    STUB
+                );
+                for stub in ignored {
+                    println!(
+                        "  {} {:?}",
+                        report::paint(Kind::Warning, format!("{:<8}", "ignored")),
+                        stub
+                    );
+                }
+                println!();
+            }
+        }
+
+        match problems {
+            0 => Ok(()),
+            n => Err(anyhow!("found {} problems", n)),
+        }
+    }
+
+    /// recreates the missing STUB files
+    pub fn fix_stubs(&self, ctx: &Ctx) -> Result<()> {
+        for dir in self.missing_stubs(ctx) {
+            report::action(Kind::Created, "creating stub", &[("dir", &dir)]);
+            let _ = fs::File::create(dir.join(STUB))?;
+        }
+        Ok(())
+    }
+
+    fn status_table(&self, ctx: &Ctx) -> Result<()> {
+        let shadowed = self.shadowed(ctx);
+        let mut rows = Vec::new();
+        for name in self.required_conf.modules.iter().rev() {
+            let module = self.modules.get(name).expect("checked in Profile::new");
+            let mut entries = module.entries(ctx).collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.src.cmp(&b.src));
+            for e in entries {
+                let state = match shadowed.get(&(name.clone(), e.src.clone())) {
+                    Some(winner) => (Kind::Skipped, format!("shadowed by {}", winner)),
+                    None => {
+                        let state = e.state()?;
+                        (state.kind(), state.name().to_owned())
+                    }
+                };
+                rows.push(vec![
+                    (Kind::Skipped, name.clone()),
+                    state,
+                    (Kind::Skipped, e.src.to_string_lossy().into_owned()),
+                ]);
+            }
+        }
+        for path in &self.required_conf.absent {
+            let e = self.absent_entry(path, ctx)?;
+            let (kind, state) = if e.src.exists() || e.src.is_symlink() {
+                (Kind::Warning, "present")
+            } else {
+                (Kind::Created, "removed")
+            };
+            rows.push(vec![
+                (Kind::Skipped, "(absent)".to_owned()),
+                (kind, state.to_owned()),
+                (Kind::Skipped, e.src.to_string_lossy().into_owned()),
+            ]);
+        }
+
+        println!();
+        report::table(&["module", "state", "path"], &rows);
+        Ok(())
+    }
+
+    /// modules in the repo that are not used by any profile
+    pub fn orphans(&self, ctx: &Ctx) -> Vec<&Module> {
+        let mut orphans = self
+            .modules
+            .values()
+            .filter(|m| m.module_dir.starts_with(&ctx.canon_repo))
+            .filter(|m| {
+                !ctx.conf
+                    .profiles
+                    .iter()
+                    .chain([&self.active_conf, &self.required_conf])
+                    .any(|p| p.modules.contains(&m.name))
+            })
+            .collect::<Vec<_>>();
+        orphans.sort_by(|a, b| a.name.cmp(&b.name));
+        orphans
+    }
+
+    /// deletes the orphaned modules from the repo (after asking)
+    pub fn prune_orphans(&self, ctx: &Ctx) -> Result<()> {
+        let orphans = self.orphans(ctx);
+        if orphans.is_empty() {
+            report::skipped("no orphaned modules");
+            return Ok(());
+        }
+
+        let names = orphans
+            .iter()
+            .map(|m| m.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !confirm(format!("delete orphaned modules {} from the repo?", names))? {
+            return Ok(());
+        }
+
+        for m in orphans {
+            report::action(Kind::Dumped, "deleting module", &[("path", &m.module_dir)]);
+            ctx.remove_dir_all(&m.module_dir, true)?;
+        }
+        Ok(())
+    }
+
+    /// a profile with the modules and absent paths of every profile in the config. errors if
+    /// two profiles would link the same path from different modules
+    pub fn all_profiles(&self, ctx: &Ctx) -> Result<ProfileDesc> {
+        let mut owners: HashMap<PathBuf, (&str, &str)> = HashMap::new();
+        let mut combined = ProfileDesc {
+            name: self.active_conf.name.clone(),
+            modules: Vec::new(),
+            absent: Vec::new(),
+        };
+
+        for prof in ctx.conf.profiles.iter() {
+            let mut linked = HashMap::new();
+            for name in prof.modules.iter() {
+                let Some(m) = self.modules.get(name) else {
+                    return Err(anyhow!(
+                        "module '{}' of profile '{}' not found",
+                        name,
+                        &prof.name
+                    ));
+                };
+                // later modules have higher precedence
+                for e in m.entries(ctx) {
+                    linked.insert(e.src, name.as_str());
+                }
+            }
+
+            for (src, module) in linked {
+                match owners.get(&src) {
+                    Some((other_prof, other)) if *other != module => {
+                        return Err(anyhow!(
+                            "profiles '{}' and '{}' link {:?} from different modules ('{}' and '{}')",
+                            other_prof,
+                            &prof.name,
+                            &src,
+                            other,
+                            module
+                        ));
+                    }
+                    Some(_) => (),
+                    None => {
+                        owners.insert(src, (&prof.name, module));
+                    }
+                }
+            }
 
-        match (e.src.exists(), e.src.is_symlink()) {
-            (false, false) => {
-                println!(
-                    "creating symlink\n  src: {:?}\n  dst: {:?}",
-                    &e.src, &e.dest
-                );
-                e.symlink_to_src(ctx)?;
-                return Ok(());
+            for name in prof.modules.iter() {
+                if !combined.modules.contains(name) {
+                    combined.modules.push(name.clone());
+                }
             }
-            (true, true) => {
-                if e.src.canonicalize()? == e.dest {
-                    return Ok(());
+            for path in prof.absent.iter() {
+                if !combined.absent.contains(path) {
+                    combined.absent.push(path.clone());
                 }
             }
-            _ => {}
-        }
-
-        println!(
-            "creating symlink\n  src: {:?}\n  dst: {:?}",
-            &e.src, &e.dest
-        );
-
-        if !force {
-            return Err(anyhow!(
-                "there is already a file/dir at: {:?}. use -f flag to force sync",
-                &e.src
-            ));
         }
 
-        println!(
-            "moving contents to dump\n  src: {:?}\n  dump: {:?}",
-            &e.src, &ctx.dump_dir
-        );
-
-        e.dump(ctx)?;
-        println!();
-        Ok(())
+        Ok(combined)
     }
 
     pub fn validate(&self) -> Result<()> {
+        let _timer = stats::timer(|| "validate".to_owned());
         let home = PathBuf::from("home");
         let mut dirs = HashMap::new();
         for m in self.modules.values() {
@@ -217,25 +1754,353 @@ impl Profile {
             }
         }
 
+        // dirs added before the policy was set stay linked as a whole
+        for m in self
+            .modules
+            .values()
+            .filter(|m| m.dir_policy == DirPolicy::Individual)
+        {
+            let whole = m
+                .home_entries
+                .iter()
+                .map(|p| m.module_dir.join(&m.home).join(p))
+                .chain(m.non_home_entries.iter().map(|p| m.module_dir.join(p)))
+                .filter(|p| p.join(STUB).exists())
+                .collect::<Vec<_>>();
+            if !whole.is_empty() {
+                report::warning(format!(
+                    "module '{}' has dir_policy = \"individual\", but these dirs are linked as a whole: {:?}. remove and add them again to link their files",
+                    &m.name, &whole
+                ));
+            }
+        }
+
+        // aliases are linked like entries, so they can not overlap with any entry
+        let tracked = self
+            .modules
+            .values()
+            .flat_map(|m| {
+                m.home_entries
+                    .iter()
+                    .map(|p| home.join(p))
+                    .chain(m.non_home_entries.iter().cloned())
+                    .map(move |p| (p, &m.name))
+            })
+            .collect::<Vec<_>>();
+        let mut aliased = HashMap::new();
+        for m in self.modules.values() {
+            for (_, alias) in &m.aliases {
+                let a = alias.clone().relative();
+                if let Some((p, name)) = tracked
+                    .iter()
+                    .find(|(p, _)| p.starts_with(&a) || a.starts_with(p))
+                {
+                    return Err(anyhow!(
+                        "alias {:?} from module {} overlaps with {:?} from module {}",
+                        &a,
+                        &m.name,
+                        p,
+                        name
+                    ));
+                }
+                if let Some(name) = aliased.insert(a.clone(), &m.name) {
+                    return Err(anyhow!(
+                        "alias {:?} is used by module {} and module {}",
+                        &a,
+                        name,
+                        &m.name
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// lets the user choose one of the active modules (highest precedence first)
+    pub fn pick_module(&self) -> Result<String> {
+        let names = self.active_conf.modules.iter().rev().collect::<Vec<_>>();
+        let options = names
+            .iter()
+            .map(|name| {
+                let m = self.modules.get(*name).expect("checked in Profile::new");
+                format!(
+                    "{} ({} entries)",
+                    name,
+                    m.home_entries.len() + m.non_home_entries.len()
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let i = pick("add to which module?", &options)?;
+        Ok(names[i].clone())
+    }
+
+    /// adds a module to the active profile (in the config and the profile file) and links
+    /// its entries. it gets the lowest precedence unless placed before/after another module
+    pub fn activate(
+        &mut self,
+        name: &str,
+        before: Option<&str>,
+        after: Option<&str>,
+        ctx: &Ctx,
+    ) -> Result<()> {
+        if self.active_conf.modules.iter().any(|m| m == name) {
+            report::unchanged(format!("module '{}' is already active", name));
+            return Ok(());
+        }
+        if !self.modules.contains_key(name) {
+            return Err(Error::ModuleNotFound(name.to_owned()).into());
+        }
+        if self.active_conf.modules != self.required_conf.modules {
+            return Err(anyhow!(
+                "the modules of profile '{}' changed since the last sync. sync before activating modules",
+                &self.required_conf.name
+            ));
+        }
+
+        let position = |other: &str| {
+            self.active_conf
+                .modules
+                .iter()
+                .position(|m| m == other)
+                .with_context(|| format!("module '{}' is not active", other))
+        };
+        let pos = match (before, after) {
+            (Some(other), _) => position(other)?,
+            (_, Some(other)) => position(other)? + 1,
+            (None, None) => 0,
+        };
+
+        self.active_conf.modules.insert(pos, name.to_owned());
+        self.required_conf.modules = self.active_conf.modules.clone();
+        ctx.save_profile_modules(&self.required_conf.name, &self.required_conf.modules)?;
+        fs::write(
+            &ctx.profile_file,
+            toml::to_string_pretty(&self.required_conf)?,
+        )?;
+        report::action(
+            Kind::Created,
+            format!("activated module '{}'", name),
+            &[("config", &ctx.config_file)],
+        );
+
+        self.sync_module(name, ctx)
+    }
+
+    /// links the entries of an active module that are not shadowed by a module with higher
+    /// precedence. links from modules with lower precedence are replaced
+    fn sync_module(&self, name: &str, ctx: &Ctx) -> Result<()> {
+        let pos = self
+            .active_conf
+            .modules
+            .iter()
+            .position(|m| m == name)
+            .expect("module must be active");
+        let module = self.modules.get(name).expect("checked in Profile::new");
+        let lower = self.active_conf.modules[..pos]
+            .iter()
+            .map(|m| self.modules.get(m).expect("checked in Profile::new"))
+            .collect::<Vec<_>>();
+        let higher = self.active_conf.modules[pos + 1..]
+            .iter()
+            .map(|m| self.modules.get(m).expect("checked in Profile::new"))
+            .collect::<Vec<_>>();
+
+        let hashes = match module.mode {
+            LinkMode::Symlink => None,
+            LinkMode::Copy => Some(HashState::load(ctx, name)?),
+        };
+        for e in module.entries(ctx) {
+            if higher.iter().any(|m| m.contains(&e)) {
+                continue;
+            }
+
+            if let Some(m) = lower.iter().find(|m| m.contains(&e)) {
+                let shadowed = m.entry_from_relative(&e.relative, ctx);
+                if shadowed.state()? == EntryState::Linked {
+                    report::action(
+                        Kind::Dumped,
+                        format!("unlinking path from module '{}'", &m.name),
+                        &[("src", &e.src)],
+                    );
+                    shadowed.rm_src_file(ctx)?;
+                }
+            }
+
+            let actions = Self::entry_action(e, IfExists::Error, hashes.as_ref())?;
+            self.apply(actions.into_iter().collect(), false, ctx)?;
+        }
         Ok(())
     }
 
-    pub fn add(&mut self, src: impl AsRef<str>, ctx: &Ctx, dest: impl AsRef<str>) -> Result<()> {
+    /// returns where the path ended up in the repo (none if nothing was added)
+    pub fn add(
+        &mut self,
+        src: impl AsRef<str>,
+        ctx: &Ctx,
+        dest: impl AsRef<str>,
+        copy_only: bool,
+        symlinks: SymlinkSrc,
+        relative_to: Option<&str>,
+    ) -> Result<Option<PathBuf>> {
         let src = src.as_ref();
         let dest = dest.as_ref();
         let Some(pos) = self.active_conf.modules.iter().position(|n| n == dest) else {
-            return Err(anyhow!("module {} is not active", dest));
+            return Err(Error::ModuleNotActive(dest.to_owned()).into());
         };
         let dest_module = self.modules.get(dest).expect("checked above");
+        if dest_module.read_only {
+            return Err(Error::ModuleReadOnly(dest.to_owned()).into());
+        }
+        // checked before anything is moved, so that a file is never half added
+        let writable = unistd::faccessat(
+            None,
+            &dest_module.module_dir,
+            unistd::AccessFlags::W_OK,
+            nix::fcntl::AtFlags::AT_EACCESS,
+        )
+        .is_ok();
+        if !writable {
+            return Err(anyhow!(
+                "module '{}' is read-only ({:?} is not writable). add not supported",
+                dest,
+                &dest_module.module_dir
+            ));
+        }
 
-        let e = match dest_module.entry_from_src(dest_module.resolve_path(src, ctx)?, ctx) {
-            Ok(e) => e,
-            Err(PathResolutionError::InRepo) => {
-                println!("the path {} is already in the repo.", src);
-                return Ok(());
+        let path = Module::resolve_path(src, ctx)?;
+        // a path in a symlinked dir in home resolves outside of home
+        let path = match ctx.classify_as {
+            Some(PathKind::Home) if !path.starts_with(dest_module.home_dir(ctx)) => {
+                let lexical = Module::lexical_path(src, ctx)?;
+                match lexical.starts_with(dest_module.home_dir(ctx)) {
+                    true => lexical,
+                    false => path,
+                }
+            }
+            _ => path,
+        };
+        // the home entries of a module are in the home of its user
+        if let Some(user) = Module::tilde_user(src) {
+            let Some(owner) = dest_module.owner.as_ref() else {
+                return Err(anyhow!(
+                    "'{}' is in the home of user '{}'. add it to a module with user = \"{}\"",
+                    src,
+                    user,
+                    user
+                ));
+            };
+            if owner.name != user {
+                return Err(anyhow!(
+                    "'{}' is in the home of user '{}', but module '{}' is for user '{}'",
+                    src,
+                    user,
+                    dest,
+                    &owner.name
+                ));
+            }
+        }
+        // a staged path is tracked as the path it has on the real system
+        let logical = match relative_to {
+            Some(root) => {
+                let root = Module::resolve_path(root, ctx)?;
+                let Ok(stripped) = path.strip_prefix(&root) else {
+                    return Err(anyhow!("{:?} is not inside {:?}", &path, &root));
+                };
+                PathBuf::from("/").join(stripped)
+            }
+            None => path.clone(),
+        };
+        self.check_own_dirs(&path, ctx)?;
+        ctx.check_denied(&logical)?;
+        if dest_module.dir_policy == DirPolicy::Individual && !path.is_symlink() && path.is_dir() {
+            return Err(anyhow!(
+                "module '{}' links the files of dirs separately (dir_policy = \"individual\"). add the files in {:?} instead",
+                dest,
+                &path
+            ));
+        }
+        let e = match dest_module.entry_from_src_as(&logical, ctx.classify_as, ctx) {
+            Ok(e)
+                if !self
+                    .modules
+                    .values()
+                    .any(|m| path.starts_with(&m.module_dir)) =>
+            {
+                e
+            }
+            Ok(_) | Err(PathResolutionError::InRepo) => {
+                report::unchanged(format!("the path {} is already in the repo.", src));
+                return Ok(None);
+            }
+            Err(PathResolutionError::NotAbsolute) => {
+                return Err(anyhow!("path must be absolute: {:?}", &logical));
+            }
+            Err(PathResolutionError::NotInHome) => {
+                return Err(anyhow!(
+                    "{:?} is not inside home {:?}, so it can not be added with --as-home",
+                    &logical,
+                    dest_module.home_dir(ctx)
+                ));
             }
             Err(PathResolutionError::OutsideRepo) => unreachable!(),
         };
+        // it would be read back as a home entry
+        if let RelativePath::NonHome(p) = &e.relative {
+            if p.starts_with(&dest_module.home) {
+                return Err(anyhow!(
+                    "{:?} would be in the home dir {:?} of module '{}'. it can not be added as a system path",
+                    &logical,
+                    &dest_module.home,
+                    dest
+                ));
+            }
+        }
+        let e = Entry { src: path, ..e };
+        if is_symlink_loop(&e.src) {
+            let err = canonicalize(&e.src).expect_err("checked above");
+            return Err(err.context(format!("can not add '{}'", src)));
+        }
+
+        // the live symlink of an entry resolves into the repo
+        if e.src.is_symlink() {
+            if let Ok(target) = e.src.canonicalize() {
+                if target == e.dest {
+                    report::unchanged(format!("path is already maintained by configma: {}", src));
+                    return Ok(None);
+                }
+                if let Some(owner) = self
+                    .modules
+                    .values()
+                    .find(|m| target.starts_with(&m.module_dir))
+                {
+                    return Err(Error::AlreadyManaged {
+                        path: src.to_owned(),
+                        module: owner.name.clone(),
+                        target,
+                    }
+                    .into());
+                }
+            }
+            match symlinks {
+                // is_file and is_dir follow the link, so it is not clear which one is meant
+                SymlinkSrc::Refuse => {
+                    return Err(anyhow!(
+                        "{:?} is a symlink to {:?}. use --dereference to add what it points to or --track-symlink to add the link itself",
+                        &e.src,
+                        fs::read_link(&e.src)?
+                    ));
+                }
+                SymlinkSrc::Track if dest_module.mode == LinkMode::Copy => {
+                    return Err(anyhow!(
+                        "module '{}' is copied, so it can not track symlinks. use --dereference",
+                        dest
+                    ));
+                }
+                SymlinkSrc::Track | SymlinkSrc::Dereference => (),
+            }
+        }
 
         // give error when trying to add something to a module but other module already has the thing (only if other has higher precedence)
         for module in self.active_conf.modules[pos + 1..]
@@ -255,7 +2120,8 @@ impl Profile {
         }
 
         let mut p = dest_module.module_dir.clone();
-        for c in e.relative.clone().relative().parent().unwrap().components() {
+        let in_module = e.dest.strip_prefix(&dest_module.module_dir)?;
+        for c in in_module.parent().unwrap().components() {
             let std::path::Component::Normal(c) = c else {
                 unreachable!()
             };
@@ -273,61 +2139,405 @@ impl Profile {
         }
 
         if dest_module.contains(&e) {
-            println!("path is already maintained by configma: {}\n", src);
-            return Ok(());
+            report::unchanged(format!("path is already maintained by configma: {}", src));
+            return Ok(None);
+        }
+
+        // moving the file into the repo leaves the other links with the old contents
+        if !copy_only && !e.src.is_symlink() && e.src.is_file() {
+            let links = e.src.metadata()?.nlink();
+            if links > 1 {
+                report::warning(format!(
+                    "{:?} has {} hardlinks. they will not be linked to the file in the repo",
+                    &e.src, links
+                ));
+                if std::io::stdin().is_terminal() && !confirm("add anyway?")? {
+                    report::skipped(format!("skipping {}", src));
+                    return Ok(None);
+                }
+            }
         }
 
-        println!("moving path\n  src: {:?}\n  dst: {:?}\n", &e.src, &e.dest);
+        if copy_only {
+            report::action(
+                Kind::Created,
+                "copying path",
+                &[("src", &e.src), ("dst", &e.dest)],
+            );
+            e.copy_to_dest(ctx)?;
+        } else if e.src.is_symlink() && symlinks == SymlinkSrc::Dereference {
+            report::action(
+                Kind::Created,
+                "copying symlink target",
+                &[("src", &e.src), ("dst", &e.dest)],
+            );
+            e.add_dereferenced(ctx)?;
+        } else if e.src.is_symlink() {
+            report::action(
+                Kind::Created,
+                "moving symlink",
+                &[("src", &e.src), ("dst", &e.dest)],
+            );
+            e.add_symlink(ctx)?;
+        } else {
+            report::action(
+                Kind::Created,
+                "moving path",
+                &[("src", &e.src), ("dst", &e.dest)],
+            );
+            e.add(ctx)?;
+        }
+        if !copy_only {
+            self.give_to_owner(&e, &e.src, ctx)?;
+        }
 
-        e.add(ctx)?;
+        if ctx.conf.fix_permissions {
+            fix_permissions(&e.dest, ctx)?;
+        }
+        if dest_module.mode == LinkMode::Copy {
+            let mut hashes = HashState::load(ctx, dest)?;
+            hashes.hashes.insert(e.src.clone(), hash_path(&e.dest)?);
+            hashes.save(ctx, dest)?;
+        }
 
         let dest_module = self.modules.get_mut(dest).expect("checked above");
         match &e.relative {
             RelativePath::Home(p) => dest_module.home_entries.insert(p.clone()),
             RelativePath::NonHome(p) => dest_module.non_home_entries.insert(p.clone()),
         };
+        Ok(Some(e.dest))
+    }
+
+    /// downloads the url to path and adds it to the module. nothing is left at path if
+    /// the download or the add fails
+    pub fn add_from_url(
+        &mut self,
+        url: &str,
+        path: &str,
+        ctx: &Ctx,
+        dest: &str,
+    ) -> Result<Option<PathBuf>> {
+        // add checks this too, but only after the download
+        if !self.active_conf.modules.iter().any(|n| n == dest) {
+            return Err(Error::ModuleNotActive(dest.to_owned()).into());
+        }
+        let module = self.modules.get(dest).expect("checked in Profile::new");
+        let src = Module::resolve_path(path, ctx)?;
+        if src.exists() || src.is_symlink() {
+            return Err(anyhow!(
+                "there is already a file/dir at {:?}. add it without --from-url",
+                &src
+            ));
+        }
+        let e = match module.entry_from_src(&src, ctx) {
+            Ok(e) => Entry { src, ..e },
+            Err(_) => return Err(anyhow!("can not download into the repo: {:?}", &src)),
+        };
+
+        report::action(
+            Kind::Created,
+            "downloading",
+            &[("url", Path::new(url)), ("src", &e.src)],
+        );
+        let contents = ctx.download(url)?;
+
+        // written next to src and renamed, so that a failed write leaves nothing at src
+        let tmp = e
+            .src
+            .with_file_name(format!(".{}.configma-download", e.src.name()));
+        let res = e.with_priv(ctx, || {
+            fs::write(&tmp, &contents)?;
+            Ok(fs::rename(&tmp, &e.src)?)
+        });
+        if let Err(err) = res {
+            let _ = e.with_priv(ctx, || Ok(fs::remove_file(&tmp)?));
+            return Err(err.context(format!("could not write {:?}", &e.src)));
+        }
+
+        let res = self.add(path, ctx, dest, false, SymlinkSrc::Refuse, None);
+        if !matches!(res, Ok(Some(_))) {
+            e.with_priv(ctx, || Ok(fs::remove_file(&e.src)?))?;
+        }
+        res
+    }
+
+    /// errors for the home dir and for paths that configma keeps it's own stuff in (or dirs
+    /// containing them), which would be moved into themselves
+    fn check_own_dirs(&self, path: &Path, ctx: &Ctx) -> Result<()> {
+        if path == Path::new("/") {
+            return Err(anyhow!("can not add the filesystem root"));
+        }
+        if path == ctx.canon_home_dir {
+            return Err(anyhow!("can not add the home dir {:?}", path));
+        }
+        let config_dir = ctx
+            .config_dir
+            .canonicalize()
+            .unwrap_or(ctx.config_dir.clone());
+        if path.starts_with(&config_dir) || config_dir.starts_with(path) {
+            return Err(anyhow!(
+                "can not add {:?}. the config dir {:?} is managed by configma itself",
+                path,
+                &config_dir,
+            ));
+        }
+        if ctx.canon_repo.starts_with(path) {
+            return Err(anyhow!(
+                "can not add the repo or a dir containing it: {:?}",
+                path
+            ));
+        }
+        for m in self.modules.values() {
+            if m.module_dir.starts_with(path) {
+                return Err(anyhow!(
+                    "can not add the dir of module '{}' or a dir containing it: {:?}",
+                    &m.name,
+                    path
+                ));
+            }
+        }
         Ok(())
     }
 
-    pub fn remove_from_active(&mut self, src: impl AsRef<str>, ctx: &Ctx) -> Result<()> {
+    /// removes from the active module with the highest precedence. if ask is set and
+    /// several modules track the path, the user picks one
+    pub fn remove_from_active(
+        &mut self,
+        src: impl AsRef<str>,
+        ctx: &Ctx,
+        ask: bool,
+        dump_only: bool,
+    ) -> Result<PathBuf> {
         let src = src.as_ref();
-        let mut pos = None;
-        for (i, m) in self
+        let mut owners = Vec::new();
+        for m in self
             .active_conf
             .modules
             .iter()
-            .enumerate()
             .rev()
-            .map(|(i, m)| (i, self.modules.get(m).expect("checked in Profile::new")))
+            .map(|m| self.modules.get(m).expect("checked in Profile::new"))
         {
-            let e = m.entry(src, ctx)?;
+            // a path in the dir of another module is not an entry of this one
+            let Ok(e) = m.entry(src, ctx) else {
+                continue;
+            };
             if m.contains(&e) {
-                pos = Some(i);
-                break;
+                owners.push(m.name.clone());
             }
         }
-        let Some(pos) = pos else {
-            return Err(anyhow!("no active module contains '{}'", src));
+        let name = match owners.len() {
+            0 => self.link_owner(src, ctx)?,
+            1 => owners.remove(0),
+            _ if !ask => owners.remove(0),
+            _ => {
+                let i = pick(
+                    format!(
+                        "{} is tracked by several active modules. remove it from:",
+                        src
+                    ),
+                    &owners,
+                )?;
+                owners.remove(i)
+            }
         };
-        let module = self
-            .modules
-            .get(&self.active_conf.modules[pos])
-            .expect("checked above");
+        let module = self.modules.get(&name).expect("checked above");
 
         let e = module.entry(src, ctx)?;
-        self._remove(&e, ctx, module)?;
+        self._remove(&e, ctx, module, dump_only)?;
 
-        let module = self
-            .modules
-            .get_mut(&self.active_conf.modules[pos])
-            .expect("checked above");
+        let module = self.modules.get_mut(&name).expect("checked above");
         match &e.relative {
             RelativePath::Home(p) => module.home_entries.remove(p),
             RelativePath::NonHome(p) => module.non_home_entries.remove(p),
         };
 
-        self.sync_active(src, ctx)?;
-        Ok(())
+        // src may have been a path in the module, which can be gone now. another module that
+        // tracks the path gets linked (also with dump_only, as nothing was restored at src)
+        self.sync_active(e.src.to_string_lossy(), ctx)?;
+        Ok(e.dest)
+    }
+
+    /// the active module that manages the path (or a dir containing it) and where the
+    /// path is in the repo
+    pub fn managed_by(&self, path: &str, ctx: &Ctx) -> Result<Option<(&Module, PathBuf)>> {
+        let path = Module::resolve_path(path, ctx)?;
+        for m in self
+            .by_precedence(&self.active_conf.modules)
+            .map(|m| self.modules.get(m).expect("checked in Profile::new"))
+        {
+            for p in path.ancestors() {
+                let e = match m.entry_from_dest(p, ctx) {
+                    Ok(e) => e,
+                    Err(_) => match m.entry_from_src(p, ctx) {
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    },
+                };
+                if m.contains(&e) {
+                    let dest = match path.strip_prefix(p)? {
+                        rest if rest.as_os_str().is_empty() => e.dest,
+                        rest => e.dest.join(rest),
+                    };
+                    return Ok(Some((m, dest)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// finds the module that the live symlink at src points into. the module
+    /// does not need to be active
+    fn link_owner(&self, src: &str, ctx: &Ctx) -> Result<String> {
+        let path = Module::resolve_path(src, ctx)?;
+        if !path.is_symlink() {
+            return Err(anyhow!("no active module contains '{}'", src));
+        }
+
+        let target = fs::read_link(&path)?;
+        let target = path.parent().expect("must have a parent").join(target);
+
+        if let Some(m) = self.modules.values().find(|m| {
+            target.starts_with(&m.module_dir)
+                && m.entry_from_dest(&target, ctx)
+                    .map(|e| e.src == path && m.contains(&e))
+                    .unwrap_or(false)
+        }) {
+            return Ok(m.name.clone());
+        }
+
+        Err(anyhow!(
+            "no active module contains '{}'. it is a symlink to {:?} which is not tracked by any module in the repo (was the module renamed?). delete the symlink manually or restore the module",
+            src,
+            &target
+        ))
+    }
+
+    /// the files in the dir src (and it's subdirs) to add as separate entries. patterns
+    /// match the path relative to src or the file name. anything that is not a dir is
+    /// returned as is
+    pub fn expand_dir(
+        src: impl AsRef<str>,
+        ctx: &Ctx,
+        only: &[String],
+        except: &[String],
+    ) -> Result<Vec<PathBuf>> {
+        let src = src.as_ref();
+        let dir = Module::resolve_path(src, ctx)?;
+        if dir.is_symlink() || !dir.is_dir() {
+            return Ok(vec![PathBuf::from(src)]);
+        }
+        let parse = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| glob::Pattern::new(p).with_context(|| format!("bad pattern '{}'", p)))
+                .collect::<Result<Vec<_>>>()
+        };
+        let (only, except) = (parse(only)?, parse(except)?);
+        let opts = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let matches = |patterns: &[glob::Pattern], rel: &Path| {
+            patterns.iter().any(|p| {
+                p.matches_path_with(rel, opts)
+                    || rel
+                        .file_name()
+                        .map(|n| p.matches_path_with(Path::new(n), opts))
+                        .unwrap_or(false)
+            })
+        };
+
+        let mut files = Vec::new();
+        let mut filtered = 0;
+        let mut pending = vec![dir.clone()];
+        while let Some(d) = pending.pop() {
+            for e in fs::read_dir(&d)? {
+                let e = e?;
+                let ft = e.file_type()?;
+                let p = e.path();
+                if ft.is_dir() {
+                    pending.push(p);
+                    continue;
+                }
+                if !(ft.is_file() || ft.is_symlink()) {
+                    report::skipped(format!("ignoring path: {}", p.to_string_lossy()));
+                    continue;
+                }
+                let rel = p.strip_prefix(&dir)?;
+                if (!only.is_empty() && !matches(&only, rel)) || matches(&except, rel) {
+                    filtered += 1;
+                    continue;
+                }
+                files.push(p);
+            }
+        }
+        files.sort();
+        report::skipped(format!(
+            "{}: {} files matched, {} filtered out",
+            src,
+            files.len(),
+            filtered
+        ));
+        Ok(files)
+    }
+
+    /// expands a glob pattern or a directory that is not itself an entry into the tracked
+    /// entries it covers. only the entries of the named module (or all active modules) are
+    /// considered. anything else is returned as is
+    pub fn expand_tracked(
+        &self,
+        src: impl AsRef<str>,
+        ctx: &Ctx,
+        module: Option<&str>,
+    ) -> Result<Vec<PathBuf>> {
+        let src = src.as_ref();
+        let modules = self
+            .active_conf
+            .modules
+            .iter()
+            .filter(|m| module.map(|n| n == m.as_str()).unwrap_or(true))
+            .filter_map(|m| self.modules.get(m))
+            .collect::<Vec<_>>();
+
+        let mut matched = if src.contains(['*', '?', '[']) {
+            let expanded =
+                shellexpand::tilde_with_context(src, || Some(ctx.canon_home_dir.to_string_lossy()));
+            let expanded = std::env::current_dir()?.join(expanded.as_ref());
+            let pattern = glob::Pattern::new(&expanded.to_string_lossy())?;
+            let opts = glob::MatchOptions {
+                require_literal_separator: true,
+                ..Default::default()
+            };
+
+            modules
+                .iter()
+                .flat_map(|m| m.entries(ctx))
+                .map(|e| e.src)
+                .filter(|p| pattern.matches_path_with(p, opts))
+                .collect::<Vec<_>>()
+        } else {
+            let path = Module::resolve_path(src, ctx)?;
+            let is_entry = modules
+                .iter()
+                .any(|m| m.entry(src, ctx).map(|e| m.contains(&e)).unwrap_or(false));
+            if is_entry || path.is_symlink() || !path.is_dir() {
+                return Ok(vec![PathBuf::from(src)]);
+            }
+
+            modules
+                .iter()
+                .flat_map(|m| m.entries(ctx))
+                .map(|e| e.src)
+                .filter(|p| p.starts_with(&path))
+                .collect::<Vec<_>>()
+        };
+
+        if matched.is_empty() {
+            return Err(anyhow!("no tracked entries match '{}'", src));
+        }
+        matched.sort();
+        matched.dedup();
+        Ok(matched)
     }
 
     // find module using whatever user picked
@@ -335,16 +2545,22 @@ impl Profile {
     // delete entry from module in memory (just for consistency)
     // check if any other module has the same entry
     // either simlink the other module's entry, or restore entry from dump to the required location
-    pub fn remove(&mut self, src: impl AsRef<str>, ctx: &Ctx, name: impl AsRef<str>) -> Result<()> {
+    pub fn remove(
+        &mut self,
+        src: impl AsRef<str>,
+        ctx: &Ctx,
+        name: impl AsRef<str>,
+        dump_only: bool,
+    ) -> Result<PathBuf> {
         let src = src.as_ref();
         let name = name.as_ref();
         let Some(_) = self.active_conf.modules.iter().position(|n| n == name) else {
-            return Err(anyhow!("module '{}' is not active", name));
+            return Err(Error::ModuleNotActive(name.to_owned()).into());
         };
         let module = self.modules.get(name).expect("checked above");
 
         let e = module.entry(src, ctx)?;
-        self._remove(&e, ctx, module)?;
+        self._remove(&e, ctx, module, dump_only)?;
 
         let module = self.modules.get_mut(name).expect("checked above");
         match &e.relative {
@@ -352,51 +2568,162 @@ impl Profile {
             RelativePath::NonHome(p) => module.non_home_entries.remove(p),
         };
 
-        self.sync_active(src, ctx)?;
-        Ok(())
+        // src may have been a path in the module, which can be gone now. another module that
+        // tracks the path gets linked (also with dump_only, as nothing was restored at src)
+        self.sync_active(e.src.to_string_lossy(), ctx)?;
+        Ok(e.dest)
     }
 
     fn sync_active(&self, src: impl AsRef<str>, ctx: &Ctx) -> Result<()> {
         let src = src.as_ref();
         for m in self
-            .active_conf
-            .modules
-            .iter()
-            .rev()
+            .by_precedence(&self.active_conf.modules)
             .map(|m| self.modules.get(m).expect("checked in Profile::new"))
         {
             let e = m.entry(src, ctx)?;
             if m.contains(&e) {
-                self.sync_entry(&e, true, ctx)?;
-                return Ok(());
+                let hashes = match m.mode {
+                    LinkMode::Symlink => None,
+                    LinkMode::Copy => Some(HashState::load(ctx, &m.name)?),
+                };
+                let actions = Self::entry_action(e, IfExists::Overwrite, hashes.as_ref())?;
+                return self.apply(actions.into_iter().collect(), false, ctx);
             }
         }
         Ok(())
     }
 
-    fn _remove(&self, e: &Entry, ctx: &Ctx, module: &Module) -> Result<()> {
+    /// if an active module other than this one has e.src as an entry
+    fn also_tracked(&self, e: &Entry, module: &Module, ctx: &Ctx) -> Result<bool> {
+        let src = e.src.to_string_lossy();
+        for m in self
+            .active_conf
+            .modules
+            .iter()
+            .filter(|m| **m != module.name)
+            .map(|m| self.modules.get(m).expect("checked in Profile::new"))
+        {
+            if m.contains(&m.entry(&src, ctx)?) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// with dump_only, dest is moved to the dump instead of being restored at src
+    fn _remove(&self, e: &Entry, ctx: &Ctx, module: &Module, dump_only: bool) -> Result<()> {
+        if module.read_only {
+            return Err(Error::ModuleReadOnly(module.name.clone()).into());
+        }
         if module.contains(e) {
-            println!("restoring path\n  src: {:?}\n  dst: {:?}\n", e.src, e.dest,);
+            // restoring the path at src would only clobber the link of the module that wins
+            // (or get replaced by it right after), so dest is moved to the dump instead
+            let shadowed = !dump_only && self.also_tracked(e, module, ctx)?;
+            if shadowed {
+                report::action(
+                    Kind::Skipped,
+                    "another active module tracks this path, not restoring it",
+                    &[("src", &e.src)],
+                );
+            }
+            let dump_only = dump_only || shadowed;
+            if !dump_only && ctx.conf.safe_remove && ctx.has_uncommitted(&e.dest)? {
+                if std::io::stdin().is_terminal()
+                    && !confirm(format!(
+                        "{:?} has uncommitted changes. remove anyway?",
+                        &e.dest
+                    ))?
+                {
+                    return Err(anyhow!("not removing {:?}", &e.src));
+                }
+                let dump = e.dump_dest(ctx)?;
+                report::action(
+                    Kind::Dumped,
+                    "dumped uncommitted changes",
+                    &[("dst", &e.dest), ("dump", &dump)],
+                );
+            }
 
-            e.remove(ctx)?;
+            // aliases stop being linked once dest is moved away
+            let aliases = module.aliases_of(&e.relative, ctx).collect::<Vec<_>>();
+            for a in aliases.iter() {
+                if a.state()? == EntryState::Linked {
+                    report::action(Kind::Dumped, "deleting alias", &[("src", &a.src)]);
+                    a.rm_src_file(ctx)?;
+                }
+            }
 
-            // remove empty parent dirs
-            let mut parent = e.relative.clone().relative();
-            while parent.pop() && !parent.to_string_lossy().is_empty() {
-                let p = module.module_dir.join(&parent);
-                if p.read_dir()?.count() == 0 {
-                    fs::remove_dir(p)?;
+            if dump_only {
+                // whatever is at src that is not ours stays
+                if e.state()? == EntryState::Linked {
+                    report::action(Kind::Dumped, "deleting link", &[("src", &e.src)]);
+                    e.rm_src_file(ctx)?;
+                }
+                let dump = e.dump_dest(ctx)?;
+                report::action(
+                    Kind::Dumped,
+                    "moving entry to dump",
+                    &[("dst", &e.dest), ("dump", &dump)],
+                );
+                if e.dest.is_dir() && !e.dest.is_symlink() {
+                    ctx.remove_dir_all(&e.dest, true)?;
                 } else {
-                    break;
+                    fs::remove_file(&e.dest)?;
+                }
+            } else {
+                report::action(
+                    Kind::Created,
+                    "restoring path",
+                    &[("src", &e.src), ("dst", &e.dest)],
+                );
+                e.remove(ctx)?;
+                self.give_to_owner(e, &e.src, ctx)?;
+            }
+
+            set_tracked_link(&e.dest, false)?;
+            if module.mode == LinkMode::Copy {
+                let mut hashes = HashState::load(ctx, &module.name)?;
+                hashes.hashes.remove(&e.src);
+                for a in aliases.iter() {
+                    hashes.hashes.remove(&a.src);
+                }
+                hashes.save(ctx, &module.name)?;
+            }
+
+            // remove empty parent dirs (never the module dir itself)
+            if !ctx.keep_dirs {
+                for p in
+                    e.dest.ancestors().skip(1).take_while(|p| {
+                        *p != module.module_dir && p.starts_with(&module.module_dir)
+                    })
+                {
+                    if p.read_dir()?.next().is_some() {
+                        break;
+                    }
+                    fs::remove_dir(p)?;
                 }
             }
         } else {
-            return Err(anyhow!(
-                "file '{:?}' not in module '{}'",
-                &e.src,
-                module.name
-            ));
+            return Err(Error::PathNotInModule {
+                path: e.src.clone(),
+                module: module.name.clone(),
+            }
+            .into());
         }
         Ok(())
     }
 }
+
+fn human_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for unit in ["B", "KiB", "MiB", "GiB"] {
+        if size < 1024.0 {
+            return match unit {
+                "B" => format!("{} B", bytes),
+                _ => format!("{:.1} {}", size, unit),
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{:.1} TiB", size)
+}
@@ -0,0 +1,220 @@
+use std::{
+    fmt::Display,
+    io::IsTerminal,
+    path::Path,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+
+use owo_colors::OwoColorize;
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+static QUIET_SKIPS: AtomicBool = AtomicBool::new(false);
+static TRACE_RESOLUTION: AtomicBool = AtomicBool::new(false);
+/// number of -v flags
+static VERBOSITY: AtomicU8 = AtomicU8::new(0);
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+/// number of actions reported so far
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+static DUMPED: AtomicUsize = AtomicUsize::new(0);
+
+/// what kind of thing is being reported. decides the color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// something was created or put in place
+    Created,
+    /// something was moved to the dump or deleted
+    Dumped,
+    /// nothing needed to be done
+    Skipped,
+    Warning,
+    Error,
+}
+
+/// how results of commands are printed
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Text,
+    Json,
+    /// aligned columns
+    Table,
+}
+
+/// colors are only used when stdout is a terminal and NO_COLOR is not set
+pub fn init() {
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+/// hides the messages of unchanged()
+pub fn set_quiet_skips(quiet: bool) {
+    QUIET_SKIPS.store(quiet, Ordering::Relaxed);
+}
+
+/// shows the messages of trace()
+pub fn set_trace_resolution(trace: bool) {
+    TRACE_RESOLUTION.store(trace, Ordering::Relaxed);
+}
+
+/// steps of resolving a path to an entry. printed to stderr, so that stdout stays parsable
+pub fn trace(msg: impl Display) {
+    if TRACE_RESOLUTION.load(Ordering::Relaxed) {
+        eprintln!("{} {}", paint(Kind::Skipped, "trace:"), msg);
+    }
+}
+
+/// shows the messages of verbose() up to level
+pub fn set_verbosity(level: u8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+/// printed to stderr with at least level -v flags
+pub fn verbose(level: u8, msg: impl Display) {
+    if VERBOSITY.load(Ordering::Relaxed) >= level {
+        eprintln!("{} {}", paint(Kind::Skipped, "verbose:"), msg);
+    }
+}
+
+/// prints the messages of action(), skipped() and warning() to stderr, so that stdout
+/// only has the output meant for tools
+pub fn set_to_stderr(to_stderr: bool) {
+    TO_STDERR.store(to_stderr, Ordering::Relaxed);
+}
+
+fn out(msg: impl Display) {
+    match TO_STDERR.load(Ordering::Relaxed) {
+        true => eprintln!("{}", msg),
+        false => println!("{}", msg),
+    }
+}
+
+pub fn paint(kind: Kind, s: impl Display) -> String {
+    if !COLOR.load(Ordering::Relaxed) {
+        return s.to_string();
+    }
+    match kind {
+        Kind::Created => s.green().to_string(),
+        Kind::Dumped => s.yellow().to_string(),
+        Kind::Skipped => s.dimmed().to_string(),
+        Kind::Warning => s.yellow().bold().to_string(),
+        Kind::Error => s.red().bold().to_string(),
+    }
+}
+
+/// prints a title followed by aligned 'label: path' rows
+pub fn action(kind: Kind, title: impl Display, paths: &[(&str, &Path)]) {
+    match kind {
+        Kind::Created => CREATED.fetch_add(1, Ordering::Relaxed),
+        Kind::Dumped => DUMPED.fetch_add(1, Ordering::Relaxed),
+        _ => 0,
+    };
+    out(paint(kind, title));
+
+    let width = paths.iter().map(|(l, _)| l.len() + 1).max().unwrap_or(0);
+    for (label, path) in paths {
+        let label = format!("{}:", label);
+        out(format!(
+            "  {} {:?}",
+            paint(Kind::Skipped, format!("{:<width$}", label)),
+            path
+        ));
+    }
+    out("");
+}
+
+/// (created, dumped) actions reported so far
+pub fn counts() -> (usize, usize) {
+    (
+        CREATED.load(Ordering::Relaxed),
+        DUMPED.load(Ordering::Relaxed),
+    )
+}
+
+pub fn skipped(msg: impl Display) {
+    out(format!("{}\n", paint(Kind::Skipped, msg)));
+}
+
+/// like skipped, for things that were already done (by an earlier run)
+pub fn unchanged(msg: impl Display) {
+    if !QUIET_SKIPS.load(Ordering::Relaxed) {
+        skipped(msg);
+    }
+}
+
+pub fn warning(msg: impl Display) {
+    out(format!("{} {}\n", paint(Kind::Warning, "Warning:"), msg));
+}
+
+pub fn error(err: &anyhow::Error) {
+    eprintln!("{} {:?}", paint(Kind::Error, "Error:"), err);
+}
+
+nix::ioctl_read_bad!(tiocgwinsz, nix::libc::TIOCGWINSZ, nix::libc::winsize);
+
+/// width of the terminal. None if stdout is not a terminal
+pub fn term_width() -> Option<usize> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let mut size = nix::libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: size is a valid winsize for the ioctl to write into
+    unsafe { tiocgwinsz(nix::libc::STDOUT_FILENO, &mut size) }.ok()?;
+    (size.ws_col > 0).then_some(size.ws_col as usize)
+}
+
+/// prints rows in aligned columns without borders. the last column is shortened in the
+/// middle to fit the terminal
+pub fn table(header: &[&str], rows: &[Vec<(Kind, String)>]) {
+    let mut widths = header.iter().map(|h| h.len()).collect::<Vec<_>>();
+    for row in rows {
+        for (w, (_, cell)) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let last = widths.len() - 1;
+    if let Some(width) = term_width() {
+        let used = widths[..last].iter().map(|w| w + 2).sum::<usize>();
+        widths[last] = widths[last].min(width.saturating_sub(used).max(10));
+    }
+
+    let line = |cells: Vec<(Kind, String)>| {
+        let mut out = Vec::new();
+        for (i, (kind, cell)) in cells.into_iter().enumerate() {
+            let cell = match i == last {
+                true => shorten(&cell, widths[i]),
+                false => cell,
+            };
+            let pad = widths[i].saturating_sub(cell.chars().count());
+            out.push(format!("{}{}", paint(kind, cell), " ".repeat(pad)));
+        }
+        println!("{}", out.join("  ").trim_end());
+    };
+    line(
+        header
+            .iter()
+            .map(|h| (Kind::Skipped, h.to_uppercase()))
+            .collect(),
+    );
+    for row in rows {
+        line(row.clone());
+    }
+}
+
+/// cuts out the middle of s so that it is at most width chars long
+fn shorten(s: &str, width: usize) -> String {
+    let len = s.chars().count();
+    if len <= width {
+        return s.to_owned();
+    }
+    let keep = width.saturating_sub(1);
+    let head = keep / 2;
+    let tail = keep - head;
+    let start = s.chars().take(head).collect::<String>();
+    let end = s.chars().skip(len - tail).collect::<String>();
+    format!("{}…{}", start, end)
+}
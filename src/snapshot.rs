@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Ctx,
+    entry::Convenience,
+    profile::Profile,
+    report::{self, Kind, Output},
+};
+
+/// number of snapshots kept in the config dir
+const KEEP: usize = 20;
+
+/// what a sync left on the filesystem
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Snapshot {
+    pub profile: String,
+    /// keyed by src
+    pub entries: BTreeMap<PathBuf, SnapshotEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEntry {
+    pub module: String,
+    pub dest: PathBuf,
+    /// where the symlink at src points to (if it is a symlink)
+    pub target: Option<PathBuf>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SnapshotDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub retargeted: Vec<Retargeted>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Retargeted {
+    pub src: PathBuf,
+    pub was: Option<PathBuf>,
+    pub now: Option<PathBuf>,
+}
+
+impl Snapshot {
+    fn dir(ctx: &Ctx) -> PathBuf {
+        ctx.config_dir.join("snapshots")
+    }
+
+    /// the entries of the required profile and what is at their src right now
+    pub fn take(profile: &Profile, ctx: &Ctx) -> Self {
+        let mut entries = BTreeMap::new();
+        for name in profile.required_conf.modules.iter().rev() {
+            let module = profile.modules.get(name).expect("checked in Profile::new");
+            if module.unmet.is_some() {
+                continue;
+            }
+            for e in module.entries(ctx) {
+                // modules with higher precedence come first
+                if entries.contains_key(&e.src) {
+                    continue;
+                }
+                let target = fs::read_link(&e.src).ok();
+                entries.insert(
+                    e.src,
+                    SnapshotEntry {
+                        module: name.clone(),
+                        dest: e.dest,
+                        target,
+                    },
+                );
+            }
+        }
+
+        Self {
+            profile: profile.required_conf.name.clone(),
+            entries,
+        }
+    }
+
+    /// saves the snapshot next to the older ones (named like the dump dir of this run)
+    /// and removes the oldest ones
+    pub fn save(&self, ctx: &Ctx) -> Result<()> {
+        let dir = Self::dir(ctx);
+        fs::create_dir_all(&dir)?;
+        let name = format!("{}.toml", ctx.dump_base.as_path().name());
+        fs::write(dir.join(name), toml::to_string_pretty(self)?)?;
+
+        let snapshots = Self::list(&dir)?;
+        for old in snapshots.iter().take(snapshots.len().saturating_sub(KEEP)) {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+
+    pub fn latest(ctx: &Ctx) -> Result<Option<Self>> {
+        let dir = Self::dir(ctx);
+        if !dir.exists() {
+            return Ok(None);
+        }
+        match Self::list(&dir)?.last() {
+            Some(path) => Ok(Some(toml::from_str(&fs::read_to_string(path)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// oldest first
+    fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut snapshots = fs::read_dir(dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        snapshots.retain(|p| p.extension().map(|e| e == "toml").unwrap_or(false));
+        snapshots.sort();
+        Ok(snapshots)
+    }
+
+    pub fn diff(&self, now: &Self) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        for (src, e) in now.entries.iter() {
+            match self.entries.get(src) {
+                None => diff.added.push(src.clone()),
+                Some(old) if old.target != e.target => diff.retargeted.push(Retargeted {
+                    src: src.clone(),
+                    was: old.target.clone(),
+                    now: e.target.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+        for src in self.entries.keys() {
+            if !now.entries.contains_key(src) {
+                diff.removed.push(src.clone());
+            }
+        }
+        diff
+    }
+}
+
+impl SnapshotDiff {
+    pub fn print(&self, ctx: &Ctx) -> Result<()> {
+        if ctx.output == Output::Json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+            return Ok(());
+        }
+
+        if self.added.is_empty() && self.removed.is_empty() && self.retargeted.is_empty() {
+            report::skipped("nothing changed since the last sync");
+            return Ok(());
+        }
+        for src in self.added.iter() {
+            println!("  {} {:?}", report::paint(Kind::Created, "added     "), src);
+        }
+        for src in self.removed.iter() {
+            println!("  {} {:?}", report::paint(Kind::Dumped, "removed   "), src);
+        }
+        for r in self.retargeted.iter() {
+            println!(
+                "  {} {:?}",
+                report::paint(Kind::Warning, "retargeted"),
+                &r.src
+            );
+            let show = |t: &Option<PathBuf>| match t {
+                Some(p) => format!("{:?}", p),
+                None => "(not a symlink)".to_owned(),
+            };
+            println!(
+                "    {} {}",
+                report::paint(Kind::Skipped, "was:"),
+                show(&r.was)
+            );
+            println!(
+                "    {} {}",
+                report::paint(Kind::Skipped, "now:"),
+                show(&r.now)
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::report::{self, Kind, Output};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+/// (phase, when it first started, how long it took in total, times it ran)
+static PHASES: Mutex<Vec<(String, Instant, Duration, usize)>> = Mutex::new(Vec::new());
+/// times the state of an entry was looked at (a few stats and readlinks each)
+static STATES: AtomicUsize = AtomicUsize::new(0);
+/// times a path was hashed (reads every file under it)
+static HASHES: AtomicUsize = AtomicUsize::new(0);
+
+/// collects the timings printed by print() (with --stats)
+pub fn set_enabled(enabled: bool, output: Output) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+    JSON.store(output == Output::Json, Ordering::Relaxed);
+}
+
+/// records how long it takes until it is dropped
+pub struct Timer {
+    phase: String,
+    start: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let taken = self.start.elapsed();
+        let Ok(mut phases) = PHASES.lock() else {
+            return;
+        };
+        // phases that run many times (like applying single entries) are added up
+        match phases.iter_mut().find(|(p, ..)| *p == self.phase) {
+            Some((_, _, total, runs)) => {
+                *total += taken;
+                *runs += 1;
+            }
+            None => phases.push((std::mem::take(&mut self.phase), self.start, taken, 1)),
+        }
+    }
+}
+
+/// None unless --stats is passed, so that the phase name is only built when needed
+pub fn timer(phase: impl FnOnce() -> String) -> Option<Timer> {
+    ENABLED.load(Ordering::Relaxed).then(|| Timer {
+        phase: phase(),
+        start: Instant::now(),
+    })
+}
+
+pub fn count_state() {
+    STATES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn count_hash() {
+    HASHES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// prints the timings to stderr (so that the output of the command stays parsable)
+pub fn print() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut phases = PHASES.lock().map(|p| p.clone()).unwrap_or_default();
+    phases.sort_by_key(|(_, start, ..)| *start);
+    let (created, dumped) = report::counts();
+    let states = STATES.load(Ordering::Relaxed);
+    let hashes = HASHES.load(Ordering::Relaxed);
+
+    if JSON.load(Ordering::Relaxed) {
+        let out = serde_json::json!({
+            "phases": phases
+                .iter()
+                .map(|(phase, _, taken, runs)| serde_json::json!({
+                    "phase": phase,
+                    "ms": taken.as_secs_f64() * 1000.0,
+                    "runs": runs,
+                }))
+                .collect::<Vec<_>>(),
+            "entry_states": states,
+            "hashes": hashes,
+            "created": created,
+            "dumped": dumped,
+        });
+        eprintln!("{}", out);
+        return;
+    }
+
+    let width = phases.iter().map(|(p, ..)| p.len()).max().unwrap_or(0);
+    eprintln!("{}", report::paint(Kind::Skipped, "stats:"));
+    for (phase, _, taken, runs) in phases.iter() {
+        let runs = match runs {
+            1 => String::new(),
+            n => format!(" ({} runs)", n),
+        };
+        eprintln!(
+            "  {:<width$} {:>10.3} ms{}",
+            phase,
+            taken.as_secs_f64() * 1000.0,
+            runs
+        );
+    }
+    eprintln!("  entry states looked at: {}", states);
+    eprintln!("  paths hashed:           {}", hashes);
+    eprintln!("  created / dumped:       {} / {}", created, dumped);
+}
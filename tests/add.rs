@@ -0,0 +1,149 @@
+mod common;
+
+use std::fs;
+
+use common::{s, tree, Env};
+use configma::{
+    entry::RelativePath,
+    module::{Module, PathKind},
+    profile::SymlinkSrc,
+};
+
+#[test]
+fn add_own_dirs() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+    env.write(".rc", "rc");
+
+    let targets = [
+        ("~".to_owned(), "home dir"),
+        ("/".to_owned(), "filesystem root"),
+        (s(&env.config_dir), "config dir"),
+        (s(&env.config_dir.join("config.toml")), "config dir"),
+        (s(&env.repo), "the repo"),
+        // contains the config dir as well as the repo
+        (s(env.root()), "can not add"),
+        (s(&env.repo.join("base")), "module 'base'"),
+    ];
+    for (target, why) in targets {
+        let err = profile
+            .add(&target, &ctx, "base", false, SymlinkSrc::Refuse, None)
+            .expect_err(&target);
+        assert!(format!("{:?}", err).contains(why), "{}: {:?}", target, err);
+    }
+    // nothing was moved
+    assert_eq!(fs::read_to_string(env.home.join(".rc")).unwrap(), "rc");
+    assert!(fs::read_dir(env.repo.join("base"))
+        .unwrap()
+        .next()
+        .is_none());
+}
+
+#[test]
+fn add_twice() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+    let rc = env.write(".rc", "rc");
+    profile
+        .add(s(&rc), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap()
+        .unwrap();
+    let before = tree(&env.repo);
+
+    // rc is the live symlink into the module now
+    let res = profile
+        .add(s(&rc), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap();
+    assert_eq!(res, None);
+    // and the path in the module
+    let res = profile
+        .add(
+            s(&env.in_module("base", ".rc")),
+            &ctx,
+            "base",
+            false,
+            SymlinkSrc::Refuse,
+            None,
+        )
+        .unwrap();
+    assert_eq!(res, None);
+    assert_eq!(tree(&env.repo), before);
+    assert_eq!(fs::read_link(&rc).unwrap(), env.in_module("base", ".rc"));
+}
+
+#[test]
+fn add_as_home() {
+    let env = Env::new(&["base"]);
+    let elsewhere = env.root().join("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    fs::write(elsewhere.join("foo"), "foo").unwrap();
+    std::os::unix::fs::symlink(&elsewhere, env.home.join("data")).unwrap();
+    let mut ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+    let foo = env.home.join("data/foo");
+
+    // resolved, it is outside of home
+    let module = profile.modules.get("base").unwrap();
+    let guessed = module
+        .entry_from_src(Module::resolve_path(s(&foo), &ctx).unwrap(), &ctx)
+        .unwrap();
+    assert!(matches!(guessed.relative, RelativePath::NonHome(_)));
+
+    ctx.classify_as = Some(PathKind::Home);
+    let dest = profile
+        .add(s(&foo), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(dest, env.in_module("base", "data/foo"));
+    let e = profile
+        .modules
+        .get("base")
+        .unwrap()
+        .entry(s(&foo), &ctx)
+        .unwrap();
+    assert_eq!(e.relative, RelativePath::Home("data/foo".into()));
+    assert_eq!(e.dest, dest);
+    assert_eq!(fs::read_link(elsewhere.join("foo")).unwrap(), dest);
+
+    // paths that are not in home at all are refused
+    fs::write(elsewhere.join("bar"), "bar").unwrap();
+    let err = profile
+        .add(
+            s(&elsewhere.join("bar")),
+            &ctx,
+            "base",
+            false,
+            SymlinkSrc::Refuse,
+            None,
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("--as-home"), "{:?}", err);
+}
+
+#[test]
+fn add_as_root() {
+    let env = Env::new(&["base"]);
+    let mut ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+    let rc = env.write(".rc", "rc");
+
+    ctx.classify_as = Some(PathKind::Root);
+    let dest = profile
+        .add(s(&rc), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap()
+        .unwrap();
+    let absolute = rc.strip_prefix("/").unwrap();
+    assert_eq!(dest, env.repo.join("base").join(absolute));
+    let e = profile
+        .modules
+        .get("base")
+        .unwrap()
+        .entry(s(&rc), &ctx)
+        .unwrap();
+    assert_eq!(e.relative, RelativePath::NonHome(absolute.to_path_buf()));
+    assert_eq!(e.dest, dest);
+    assert_eq!(fs::read_link(&rc).unwrap(), dest);
+    assert!(!env.in_module("base", ".rc").exists());
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use std::fs;
+
+use common::{s, Env};
+use configma::{
+    entry::{HOME, LINKS},
+    profile::{IfExists, Profile, SymlinkSrc},
+};
+
+#[test]
+fn add_sync_remove() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+
+    let rc = env.write(".testrc", "contents");
+    let dest = profile
+        .add(s(&rc), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(dest, env.in_module("base", ".testrc"));
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "contents");
+    assert_eq!(fs::read_link(&rc).unwrap(), dest);
+
+    // a fresh sync keeps the link that add made
+    fs::remove_file(&rc).unwrap();
+    let profile_ = Profile::load(None, &ctx).unwrap();
+    profile_.sync(IfExists::Error, false, false, &ctx).unwrap();
+    assert_eq!(fs::read_link(&rc).unwrap(), dest);
+
+    profile.remove(s(&rc), &ctx, "base", false).unwrap();
+    assert!(!rc.is_symlink());
+    assert_eq!(fs::read_to_string(&rc).unwrap(), "contents");
+    assert!(!dest.exists());
+}
+
+#[test]
+fn add_sync_remove_dir() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+
+    env.write(".config/nvim/init.lua", "lua");
+    let dir = env.home.join(".config/nvim");
+    let dest = profile
+        .add(s(&dir), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(fs::read_link(&dir).unwrap(), dest);
+    assert_eq!(fs::read_to_string(dest.join("init.lua")).unwrap(), "lua");
+
+    profile.remove(s(&dir), &ctx, "base", false).unwrap();
+    assert!(!dir.is_symlink());
+    assert_eq!(fs::read_to_string(dir.join("init.lua")).unwrap(), "lua");
+    assert!(!dest.exists());
+}
+
+#[test]
+fn add_remove_external_module() {
+    let env = Env::new(&[]);
+    // the module is the dir named like it in path
+    let ext = env.root().join("elsewhere/ext");
+    fs::create_dir_all(&ext).unwrap();
+    let ctx = env.ctx(&format!(
+        "[[modules]]\nname = \"ext\"\npath = {:?}\n[[profiles]]\nname = \"test\"\nmodules = [\"ext\"]\n",
+        s(ext.parent().unwrap())
+    ));
+    let mut profile = env.synced(&ctx);
+
+    let rc = env.write(".rc", "rc");
+    let dest = profile
+        .add(s(&rc), &ctx, "ext", false, SymlinkSrc::Refuse, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(dest, ext.join(HOME).join(".rc"));
+    assert_eq!(fs::read_link(&rc).unwrap(), dest);
+    assert!(!dest.starts_with(&env.repo));
+
+    // by the path in the module too
+    profile.remove(s(&dest), &ctx, "ext", false).unwrap();
+    assert!(!rc.is_symlink());
+    assert_eq!(fs::read_to_string(&rc).unwrap(), "rc");
+    assert!(!dest.exists());
+}
+
+#[test]
+fn add_remove_tracked_symlink() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+    let target = env.write("target", "target");
+    let link = env.home.join(".link");
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    let dest = profile
+        .add(s(&link), &ctx, "base", false, SymlinkSrc::Track, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(fs::read_link(&dest).unwrap(), target);
+    let links = dest.parent().unwrap().join(LINKS);
+    assert_eq!(fs::read_to_string(&links).unwrap(), ".link\n");
+
+    // still an entry when the module is loaded again
+    let mut profile = Profile::load(None, &ctx).unwrap();
+    assert!(profile.tracks("base", &s(&link), &ctx));
+
+    profile.remove(s(&link), &ctx, "base", false).unwrap();
+    assert_eq!(fs::read_link(&link).unwrap(), target);
+    assert!(!links.exists());
+}
+
+#[test]
+fn untracked_symlinks_in_modules_are_ignored() {
+    let env = Env::new(&["base"]);
+    let target = env.write("target", "target");
+    let stray = env.in_module("base", ".stray");
+    fs::create_dir_all(stray.parent().unwrap()).unwrap();
+    std::os::unix::fs::symlink(&target, &stray).unwrap();
+    std::os::unix::fs::symlink(&target, env.repo.join("base/stray")).unwrap();
+    let ctx = env.ctx(&Env::conf(&["base"]));
+
+    let profile = env.synced(&ctx);
+    assert!(!profile.tracks("base", &s(&env.home.join(".stray")), &ctx));
+    assert!(!env.home.join(".stray").is_symlink());
+    assert!(profile
+        .modules
+        .get("base")
+        .unwrap()
+        .entries(&ctx)
+        .next()
+        .is_none());
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::fs;
+
+use clap::Parser;
+use common::{s, Env};
+use configma::{archive, entry::HOME, Cli};
+
+#[test]
+fn export_import_round_trip() {
+    // a repo module, a module with a custom path and a path in home in the config
+    let from = Env::new(&["base"]);
+    let ext = from.root().join("elsewhere/ext");
+    for (dest, contents) in [
+        (from.in_module("base", ".rc"), "base"),
+        (ext.join(HOME).join(".extrc"), "ext"),
+    ] {
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&dest, contents).unwrap();
+    }
+    fs::create_dir(from.home.join("data")).unwrap();
+    let ctx = from.ctx(&format!(
+        "home_roots = [{:?}]\n[[modules]]\nname = \"ext\"\npath = {:?}\n[[profiles]]\nname = \"test\"\nmodules = [\"base\", \"ext\"]\n",
+        s(&from.home.join("data")),
+        s(ext.parent().unwrap()),
+    ));
+    let out = from.root().join("export.tar");
+    archive::export(&ctx, &s(&out)).unwrap();
+
+    let to = Env::new(&[]);
+    fs::create_dir(to.home.join("data")).unwrap();
+    let cli = Cli::try_parse_from([
+        "configma".to_owned(),
+        "--config-dir".to_owned(),
+        s(&to.config_dir),
+        "import".to_owned(),
+        s(&out),
+        s(&to.repo),
+    ])
+    .unwrap();
+    archive::import(&cli, &Env::user(), &s(&out), Some(&s(&to.repo))).unwrap();
+
+    let conf = fs::read_to_string(to.config_dir.join("config.toml")).unwrap();
+    assert!(conf.contains("\"~/data\""), "{}", conf);
+    assert!(!conf.contains(&s(from.root())), "{}", conf);
+    assert!(
+        !conf.contains("{repo}") && !conf.contains("{modules}"),
+        "{}",
+        conf
+    );
+
+    let ctx = to.ctx_from_file(None, Env::user());
+    assert_eq!(ctx.canon_repo, to.repo);
+    assert_eq!(ctx.home_roots[0].0, to.home.join("data"));
+    to.synced(&ctx);
+    let imported_ext = to.config_dir.join("modules/ext");
+    for (src, dest, contents) in [
+        (".rc", to.in_module("base", ".rc"), "base"),
+        (".extrc", imported_ext.join(HOME).join(".extrc"), "ext"),
+    ] {
+        let src = to.home.join(src);
+        assert_eq!(fs::read_link(&src).unwrap(), dest);
+        assert_eq!(fs::read_to_string(&src).unwrap(), contents);
+    }
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use common::{s, Env};
+
+/// runs configma batch with the config of env, returns (stdout, stderr, success)
+fn batch(env: &Env, input: &str) -> (String, String, bool) {
+    let user = Env::user();
+    let name = user.name().to_string_lossy().into_owned();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_configma"))
+        .arg("--config-dir")
+        .arg(&env.config_dir)
+        .arg("batch")
+        .env("USER", &name)
+        // as root configma wants to be run with sudo
+        .env("SUDO_USER", &name)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    (
+        String::from_utf8(out.stdout).unwrap(),
+        String::from_utf8(out.stderr).unwrap(),
+        out.status.success(),
+    )
+}
+
+#[test]
+fn batch_prints_only_json() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    env.synced(&ctx);
+    // outside of home, so that the home of the user running the tests is not touched
+    let dir = env.root().join("elsewhere");
+    fs::create_dir(&dir).unwrap();
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    fs::write(&a, "a").unwrap();
+    fs::write(&b, "b").unwrap();
+    let missing = dir.join("missing");
+
+    let input = serde_json::json!([
+        {"op": "add", "src": [s(&a)], "module": "base"},
+        {"op": "add", "src": [s(&b), s(&missing)], "module": "base"},
+        {"op": "remove", "src": [s(&a)], "module": "base"},
+    ]);
+    let (stdout, stderr, success) = batch(&env, &input.to_string());
+    assert!(!success);
+    assert!(stderr.contains("moving path"), "{}", stderr);
+
+    let outcomes = serde_json::from_str::<serde_json::Value>(&stdout).expect(&stdout);
+    let outcomes = outcomes.as_array().unwrap();
+    // stops at the failure
+    assert_eq!(outcomes.len(), 2);
+    let in_repo =
+        |p: &std::path::Path| s(&env.repo.join("base").join(p.strip_prefix("/").unwrap()));
+    assert_eq!(outcomes[0]["ok"], true);
+    assert_eq!(outcomes[0]["paths"], serde_json::json!([in_repo(&a)]));
+    // b was added before missing failed
+    assert_eq!(outcomes[1]["ok"], false);
+    assert_eq!(outcomes[1]["paths"], serde_json::json!([in_repo(&b)]));
+    assert!(outcomes[1]["error"].is_string());
+    assert_eq!(s(&fs::read_link(&b).unwrap()), in_repo(&b));
+}
@@ -0,0 +1,154 @@
+// not every test file uses every helper
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use configma::{
+    config::{Config, Ctx, CtxPaths},
+    entry::HOME,
+    profile::{IfExists, Profile},
+};
+use tempfile::TempDir;
+use users::User;
+
+/// a home, a repo and a config dir in a temp dir, so that nothing outside of it is touched
+pub struct Env {
+    _dir: TempDir,
+    pub home: PathBuf,
+    pub repo: PathBuf,
+    pub config_dir: PathBuf,
+}
+
+impl Env {
+    /// creates a module dir in the repo for each of modules
+    pub fn new(modules: &[&str]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let s = Self {
+            home: root.join("home"),
+            repo: root.join("repo"),
+            config_dir: root.join("config"),
+            _dir: dir,
+        };
+        for d in [&s.home, &s.repo, &s.config_dir] {
+            fs::create_dir(d).unwrap();
+        }
+        for m in modules {
+            fs::create_dir(s.repo.join(m)).unwrap();
+        }
+        s
+    }
+
+    /// the temp dir everything is in
+    pub fn root(&self) -> &Path {
+        self.home.parent().unwrap()
+    }
+
+    /// conf is the config without the repo (which is always the repo of the env)
+    pub fn ctx(&self, conf: &str) -> Ctx {
+        self.ctx_as(conf, None, Self::user())
+    }
+
+    /// like ctx, run by user (with sudo if root_user is given)
+    pub fn ctx_as(&self, conf: &str, root_user: Option<User>, user: User) -> Ctx {
+        let conf = format!("repo = {:?}\n{}", self.repo.to_string_lossy(), conf);
+        fs::write(self.config_dir.join("config.toml"), conf).unwrap();
+        self.ctx_from_file(root_user, user)
+    }
+
+    /// loads the config file in the config dir as it is
+    pub fn ctx_from_file(&self, root_user: Option<User>, user: User) -> Ctx {
+        let config_file = self.config_dir.join("config.toml");
+        let conf = fs::read_to_string(&config_file).unwrap();
+        let conf = toml::from_str::<Config>(&conf).unwrap();
+        let paths = CtxPaths {
+            home_dir: self.home.clone(),
+            config_dir: self.config_dir.clone(),
+            config_file,
+            dump_base: self.config_dir.join("dumps").join("test"),
+            repo: PathBuf::from(&conf.repo),
+        };
+        Ctx::with_paths(conf, paths, root_user, user).unwrap()
+    }
+
+    pub fn user() -> User {
+        users::get_user_by_uid(users::get_current_uid()).unwrap()
+    }
+
+    /// a config with a single profile 'test' linking modules (in this order)
+    pub fn conf(modules: &[&str]) -> String {
+        format!(
+            "modules = []\n[[profiles]]\nname = \"test\"\nmodules = {:?}\n",
+            modules
+        )
+    }
+
+    /// loads the profile 'test' and links it, so that its modules are active
+    pub fn synced(&self, ctx: &Ctx) -> Profile {
+        Profile::load(Some("test"), ctx)
+            .unwrap()
+            .sync(IfExists::Error, false, false, ctx)
+            .unwrap();
+        Profile::load(None, ctx).unwrap()
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>, contents: &str) -> PathBuf {
+        let path = self.home.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// where the home entry path is in module
+    pub fn in_module(&self, module: &str, path: impl AsRef<Path>) -> PathBuf {
+        self.repo.join(module).join(HOME).join(path)
+    }
+}
+
+/// path as a string for add and remove
+pub fn s(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+/// the contents of every dumped file named name
+pub fn dumped(env: &Env, name: &str) -> Vec<String> {
+    fn walk(dir: &Path, name: &str, found: &mut Vec<String>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for e in entries {
+            let path = e.unwrap().path();
+            if path.is_dir() {
+                walk(&path, name, found);
+            } else if path.file_name().unwrap() == name {
+                found.push(fs::read_to_string(&path).unwrap());
+            }
+        }
+    }
+    let mut found = Vec::new();
+    walk(&env.config_dir.join("dumps"), name, &mut found);
+    found
+}
+
+/// every path under dir (relative to it), with the contents of the files
+pub fn tree(dir: &Path) -> Vec<(PathBuf, Option<String>)> {
+    let mut found = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(d) = dirs.pop() {
+        for e in fs::read_dir(&d).unwrap() {
+            let path = e.unwrap().path();
+            let relative = path.strip_prefix(dir).unwrap().to_path_buf();
+            if path.is_dir() && !path.is_symlink() {
+                dirs.push(path);
+                found.push((relative, None));
+            } else {
+                found.push((relative, fs::read_to_string(&path).ok()));
+            }
+        }
+    }
+    found.sort();
+    found
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+};
+
+use common::Env;
+use configma::config::MAX_DOWNLOAD;
+
+/// serves body once on localhost without a Content-Length (the connection is closed after it)
+fn serve(body: Vec<u8>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/file", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n");
+        let _ = stream.write_all(&body);
+    });
+    url
+}
+
+#[test]
+fn download() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&Env::conf(&[]));
+    let url = serve(b"contents".to_vec());
+    assert_eq!(ctx.download(&url).unwrap(), b"contents");
+}
+
+#[test]
+fn download_is_limited_without_content_length() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&Env::conf(&[]));
+    let url = serve(vec![b'a'; MAX_DOWNLOAD + 1]);
+    let err = ctx.download(&url).unwrap_err();
+    assert!(err.to_string().contains("is larger than"), "{:?}", err);
+}
+
+#[test]
+fn download_refuses_other_schemes() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&Env::conf(&[]));
+    let err = ctx.download("file:///etc/hostname").unwrap_err();
+    assert!(err.to_string().contains("only http and https"), "{:?}", err);
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::{fs, path::Path, process::Command};
+
+use common::Env;
+
+fn git(repo: &Path, args: &[&str]) -> String {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "user.name=test", "-c", "user.email=test@test"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{:?}", out);
+    String::from_utf8(out.stdout).unwrap().trim().to_owned()
+}
+
+#[test]
+fn commit_to_branch() {
+    let env = Env::new(&["base"]);
+    git(&env.repo, &["init", "--quiet", "--initial-branch=main"]);
+    fs::write(env.repo.join("base/.keep"), "").unwrap();
+    git(&env.repo, &["add", "--all"]);
+    git(&env.repo, &["commit", "--quiet", "-m", "init"]);
+    git(&env.repo, &["branch", "other"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+
+    let rc = env.in_module("base", ".rc");
+    fs::create_dir_all(rc.parent().unwrap()).unwrap();
+    fs::write(&rc, "rc").unwrap();
+    // commit-tree needs an identity
+    for var in ["GIT_AUTHOR_NAME", "GIT_COMMITTER_NAME"] {
+        std::env::set_var(var, "test");
+    }
+    for var in ["GIT_AUTHOR_EMAIL", "GIT_COMMITTER_EMAIL"] {
+        std::env::set_var(var, "test@test");
+    }
+    ctx.commit_to_branch("other", &[rc], "add .rc").unwrap();
+
+    assert_eq!(git(&env.repo, &["show", "other:base/home/.rc"]), "rc");
+    assert_eq!(git(&env.repo, &["symbolic-ref", "--short", "HEAD"]), "main");
+    // the temporary index is in the git dir and is removed again
+    let left = fs::read_dir(env.repo.join(".git"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("configma-index"))
+        .collect::<Vec<_>>();
+    assert!(left.is_empty(), "{:?}", left);
+}
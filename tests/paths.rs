@@ -0,0 +1,68 @@
+mod common;
+
+use std::{os::unix, path::Path};
+
+use common::{s, Env};
+use configma::{
+    module::{Module, PathResolutionError},
+    profile::SymlinkSrc,
+};
+
+#[test]
+fn resolved_paths_are_absolute() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    env.write(".config/rc", "rc");
+
+    let home = s(&env.home);
+    for path in [
+        format!("{}/./.config/././rc", home),
+        format!("/{}//.config//rc", home),
+        format!("{}/.config/../.config/rc", home),
+        "~/.config/./rc".to_owned(),
+    ] {
+        let resolved = Module::resolve_path(&path, &ctx).unwrap();
+        assert_eq!(resolved, env.home.join(".config/rc"), "{}", path);
+    }
+    // relative to the dir the tests run in
+    let resolved = Module::resolve_path("./src/../Cargo.toml", &ctx).unwrap();
+    assert!(resolved.is_absolute());
+    assert!(resolved.ends_with("Cargo.toml"));
+    assert!(!resolved.to_string_lossy().contains(".."));
+}
+
+#[test]
+fn unresolvable_paths_error() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let mut profile = env.synced(&ctx);
+    let looped = env.home.join("loop");
+    unix::fs::symlink(&looped, &looped).unwrap();
+
+    for path in [
+        "".to_owned(),
+        "./no/such/dir/rc".to_owned(),
+        format!("{}/loop/rc", s(&env.home)),
+        format!("{}/loop", s(&env.home)),
+    ] {
+        let res = profile.add(&path, &ctx, "base", false, SymlinkSrc::Refuse, None);
+        assert!(res.is_err(), "{:?}: {:?}", path, res);
+    }
+}
+
+#[test]
+fn relative_src_is_not_absolute() {
+    let env = Env::new(&["base"]);
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let profile = env.synced(&ctx);
+    let module = profile.modules.get("base").unwrap();
+
+    for path in ["rc", "./rc", "../rc", ""] {
+        let res = module.entry_from_src(Path::new(path), &ctx);
+        assert!(
+            matches!(res, Err(PathResolutionError::NotAbsolute)),
+            "{:?}",
+            path
+        );
+    }
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::{fs, panic, sync::Mutex};
+
+use anyhow::anyhow;
+use common::{dumped, s, Env};
+use configma::profile::{IfExists, Profile};
+use nix::unistd;
+
+/// the effective ids are the same for every thread, so the tests can not run at once
+static EUID: Mutex<()> = Mutex::new(());
+
+/// runs f with the euid dropped to nobody (like when configma is run using sudo)
+fn as_sudo(f: impl FnOnce(&configma::config::Ctx, u32)) {
+    assert!(unistd::geteuid().is_root(), "run with --ignored as root");
+    let _lock = EUID.lock().unwrap_or_else(|e| e.into_inner());
+    let env = Env::new(&[]);
+    let root = users::get_user_by_uid(0).unwrap();
+    let nobody = users::get_user_by_name("nobody").unwrap();
+    let ctx = env.ctx_as(&Env::conf(&[]), Some(root), nobody.clone());
+
+    unistd::setegid(unistd::Gid::from_raw(nobody.primary_group_id())).unwrap();
+    unistd::seteuid(unistd::Uid::from_raw(nobody.uid())).unwrap();
+    let res = panic::catch_unwind(panic::AssertUnwindSafe(|| f(&ctx, nobody.uid())));
+    unistd::seteuid(unistd::Uid::from_raw(0)).unwrap();
+    unistd::setegid(unistd::Gid::from_raw(0)).unwrap();
+    res.unwrap();
+}
+
+#[test]
+#[ignore = "needs root"]
+fn dropped_after_error() {
+    as_sudo(|ctx, uid| {
+        let res = ctx.with_privileges(|| -> anyhow::Result<()> {
+            assert!(unistd::geteuid().is_root());
+            Err(anyhow!("failed while privileged"))
+        });
+        assert!(res.is_err());
+        assert_eq!(unistd::geteuid().as_raw(), uid);
+    });
+}
+
+#[test]
+#[ignore = "needs root"]
+fn dropped_after_panic() {
+    as_sudo(|ctx, uid| {
+        let mut escalated = false;
+        let res = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            ctx.with_privileges(|| -> anyhow::Result<()> {
+                escalated = unistd::geteuid().is_root();
+                panic!("panicked while privileged");
+            })
+        }));
+        assert!(res.is_err());
+        assert!(escalated);
+        assert_eq!(unistd::geteuid().as_raw(), uid);
+    });
+}
+
+#[test]
+#[ignore = "needs root"]
+fn audit_records_what_was_replaced() {
+    let _lock = EUID.lock().unwrap_or_else(|e| e.into_inner());
+    // paths in dirs of root need privileges (and are audited) as root too
+    let env = Env::new(&["base"]);
+    let rc = env.root().join("etc/rc");
+    let dest = env.repo.join("base").join(rc.strip_prefix("/").unwrap());
+    fs::create_dir_all(rc.parent().unwrap()).unwrap();
+    fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    fs::write(&rc, "old").unwrap();
+    fs::write(&dest, "new").unwrap();
+    let log = env.config_dir.join("audit.log");
+    let ctx = env.ctx(&format!(
+        "audit_log = {:?}\n{}",
+        s(&log),
+        Env::conf(&["base"])
+    ));
+
+    Profile::load(Some("test"), &ctx)
+        .unwrap()
+        .sync(IfExists::Overwrite, false, false, &ctx)
+        .unwrap();
+    assert_eq!(fs::read_link(&rc).unwrap(), dest);
+
+    let events = fs::read_to_string(&log)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .collect::<Vec<_>>();
+    let [dump, symlink] = &events[..] else {
+        panic!("{:?}", events);
+    };
+    assert_eq!(dump["action"], "dump");
+    assert_eq!(dump["path"], s(&rc));
+    assert_eq!(dump["old"]["kind"], "file");
+    assert_eq!(dump["old"]["size"], 3);
+    assert_eq!(symlink["action"], "symlink");
+    assert_eq!(symlink["old"]["dumped_to"], dump["target"]);
+    assert_eq!(symlink["target"], s(&dest));
+    assert_eq!(dumped(&env, "rc"), ["old"]);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::fs;
+
+use common::{dumped, s, Env};
+use configma::{entry::HOME, profile::SymlinkSrc};
+
+/// modules a and b (b wins), with .rc in the modules named in tracked
+fn with_rc(tracked: &[&str]) -> (Env, configma::config::Ctx) {
+    let env = Env::new(&["a", "b"]);
+    for m in tracked {
+        let dest = env.in_module(m, ".rc");
+        fs::create_dir_all(dest.parent().unwrap()).unwrap();
+        fs::write(&dest, m).unwrap();
+    }
+    let ctx = env.ctx(&Env::conf(&["a", "b"]));
+    (env, ctx)
+}
+
+#[test]
+fn remove_from_winner_links_loser() {
+    let (env, ctx) = with_rc(&["a", "b"]);
+    let mut profile = env.synced(&ctx);
+    let rc = env.home.join(".rc");
+    assert_eq!(fs::read_link(&rc).unwrap(), env.in_module("b", ".rc"));
+
+    profile.remove(s(&rc), &ctx, "b", false).unwrap();
+    assert_eq!(fs::read_link(&rc).unwrap(), env.in_module("a", ".rc"));
+    assert!(!env.in_module("b", ".rc").exists());
+    // it was not restored at src, so it is only in the dump
+    assert_eq!(dumped(&env, ".rc"), ["b"]);
+}
+
+#[test]
+fn remove_from_loser_keeps_winner() {
+    let (env, ctx) = with_rc(&["a", "b"]);
+    let mut profile = env.synced(&ctx);
+    let rc = env.home.join(".rc");
+
+    profile.remove(s(&rc), &ctx, "a", false).unwrap();
+    assert_eq!(fs::read_link(&rc).unwrap(), env.in_module("b", ".rc"));
+    assert!(!env.in_module("a", ".rc").exists());
+    assert_eq!(dumped(&env, ".rc"), ["a"]);
+}
+
+#[test]
+fn remove_only_in_winner_restores() {
+    let (env, ctx) = with_rc(&["b"]);
+    let mut profile = env.synced(&ctx);
+    let rc = env.home.join(".rc");
+    assert_eq!(fs::read_link(&rc).unwrap(), env.in_module("b", ".rc"));
+
+    profile.remove(s(&rc), &ctx, "b", false).unwrap();
+    assert!(!rc.is_symlink());
+    assert_eq!(fs::read_to_string(&rc).unwrap(), "b");
+    assert!(dumped(&env, ".rc").is_empty());
+}
+
+#[test]
+fn remove_only_in_loser_restores() {
+    let (env, ctx) = with_rc(&["a"]);
+    let mut profile = env.synced(&ctx);
+    let rc = env.home.join(".rc");
+    assert_eq!(fs::read_link(&rc).unwrap(), env.in_module("a", ".rc"));
+
+    profile.remove(s(&rc), &ctx, "a", false).unwrap();
+    assert!(!rc.is_symlink());
+    assert_eq!(fs::read_to_string(&rc).unwrap(), "a");
+    assert!(dumped(&env, ".rc").is_empty());
+}
+
+/// adds ~/.config/app/rc to base and removes it again (keep_dirs is --keep-dirs)
+fn add_remove_nested(conf: &str, keep_dirs: bool) -> Env {
+    let env = Env::new(&["base"]);
+    let mut ctx = env.ctx(&format!("{}{}", conf, Env::conf(&["base"])));
+    ctx.keep_dirs |= keep_dirs;
+    let mut profile = env.synced(&ctx);
+    let rc = env.write(".config/app/rc", "rc");
+    profile
+        .add(s(&rc), &ctx, "base", false, SymlinkSrc::Refuse, None)
+        .unwrap();
+    profile.remove(s(&rc), &ctx, "base", false).unwrap();
+    assert_eq!(fs::read_to_string(&rc).unwrap(), "rc");
+    env
+}
+
+#[test]
+fn remove_cleans_up_empty_dirs() {
+    let env = add_remove_nested("", false);
+    assert!(!env.in_module("base", ".config").exists());
+    assert!(!env.repo.join("base").join(HOME).exists());
+    // the module itself is kept even though it is empty now
+    assert!(env.repo.join("base").is_dir());
+}
+
+#[test]
+fn remove_keeps_empty_dirs() {
+    for (conf, keep_dirs) in [("keep_empty_dirs = true\n", false), ("", true)] {
+        let env = add_remove_nested(conf, keep_dirs);
+        assert!(env.in_module("base", ".config/app").is_dir());
+        assert!(!env.in_module("base", ".config/app/rc").exists());
+    }
+}
+
+#[test]
+fn remove_from_active_by_path_in_repo() {
+    let (env, ctx) = with_rc(&["b"]);
+    let mut profile = env.synced(&ctx);
+    let rc = env.home.join(".rc");
+
+    let dest = profile
+        .remove_from_active(s(&env.in_module("b", ".rc")), &ctx, false, false)
+        .unwrap();
+    assert_eq!(dest, env.in_module("b", ".rc"));
+    assert!(!rc.is_symlink());
+    assert_eq!(fs::read_to_string(&rc).unwrap(), "b");
+
+    // the path in the repo picks the module, even if it does not win
+    let (env, ctx) = with_rc(&["a", "b"]);
+    let mut profile = env.synced(&ctx);
+    profile
+        .remove_from_active(s(&env.in_module("a", ".rc")), &ctx, false, false)
+        .unwrap();
+    assert!(!env.in_module("a", ".rc").exists());
+    assert_eq!(
+        fs::read_link(env.home.join(".rc")).unwrap(),
+        env.in_module("b", ".rc")
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use std::io;
+
+use common::Env;
+use nix::errno::Errno;
+
+fn failing(errno: Errno, times: usize, calls: &mut usize) -> io::Result<()> {
+    *calls += 1;
+    match *calls <= times {
+        true => Err(io::Error::from_raw_os_error(errno as i32)),
+        false => Ok(()),
+    }
+}
+
+#[test]
+fn retries_transient_errors() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&format!(
+        "{}[retry]\nattempts = 3\nbackoff_ms = 1\n",
+        Env::conf(&[])
+    ));
+
+    let mut calls = 0;
+    ctx.retry(|| failing(Errno::EAGAIN, 2, &mut calls)).unwrap();
+    assert_eq!(calls, 3);
+
+    // only the last error is returned
+    let mut calls = 0;
+    let err = ctx
+        .retry(|| failing(Errno::ESTALE, 5, &mut calls))
+        .unwrap_err();
+    assert_eq!(calls, 3);
+    assert_eq!(err.raw_os_error(), Some(Errno::ESTALE as i32));
+}
+
+#[test]
+fn other_errors_fail_right_away() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&format!(
+        "{}[retry]\nattempts = 3\nbackoff_ms = 1\n",
+        Env::conf(&[])
+    ));
+
+    for errno in [Errno::EACCES, Errno::ENOENT] {
+        let mut calls = 0;
+        ctx.retry(|| failing(errno, 1, &mut calls)).unwrap_err();
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use std::fs;
+
+use common::{s, Env};
+use configma::{entry::copy_dir, profile::SymlinkSrc};
+use nix::{sys::stat::Mode, unistd};
+
+/// a dir with a file and a fifo in it
+fn with_fifo(env: &Env) -> std::path::PathBuf {
+    env.write("app/rc", "rc");
+    let dir = env.home.join("app");
+    unistd::mkfifo(&dir.join("fifo"), Mode::S_IRWXU).unwrap();
+    dir
+}
+
+#[test]
+fn copy_dir_skips_fifo() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&format!("special_files = \"skip\"\n{}", Env::conf(&[])));
+    let dir = with_fifo(&env);
+    let to = env.root().join("copy");
+
+    copy_dir(&dir, &to, &ctx).unwrap();
+    assert_eq!(fs::read_to_string(to.join("rc")).unwrap(), "rc");
+    assert!(!to.join("fifo").exists());
+}
+
+#[test]
+fn copy_dir_errors_on_fifo() {
+    let env = Env::new(&[]);
+    let ctx = env.ctx(&Env::conf(&[]));
+    let dir = with_fifo(&env);
+
+    let err = copy_dir(&dir, &env.root().join("copy"), &ctx).unwrap_err();
+    assert!(err.to_string().contains("fifo"), "{:?}", err);
+}
+
+#[test]
+fn add_copy_only_with_fifo() {
+    for (conf, ok) in [("special_files = \"skip\"\n", true), ("", false)] {
+        let env = Env::new(&["base"]);
+        let ctx = env.ctx(&format!("{}{}", conf, Env::conf(&["base"])));
+        let mut profile = env.synced(&ctx);
+        let dir = with_fifo(&env);
+
+        let res = profile.add(s(&dir), &ctx, "base", true, SymlinkSrc::Refuse, None);
+        assert_eq!(res.is_ok(), ok, "{:?}", res);
+        let copied = env.in_module("base", "app");
+        assert_eq!(copied.join("rc").exists(), ok);
+        assert!(!copied.join("fifo").exists());
+        // the original is never touched by a copy
+        assert!(!dir.is_symlink());
+        assert!(dir.join("fifo").exists());
+    }
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use std::{
+    fs,
+    os::unix::{self, fs::PermissionsExt},
+};
+
+use common::Env;
+use configma::{
+    entry::EntryState,
+    profile::{IfExists, Profile},
+};
+
+/// base tracks ~/.config/app/rc
+fn with_nested() -> Env {
+    let env = Env::new(&["base"]);
+    let dest = env.in_module("base", ".config/app/rc");
+    fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    fs::write(&dest, "rc").unwrap();
+    env
+}
+
+fn sync(env: &Env) -> anyhow::Result<()> {
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    Profile::load(Some("test"), &ctx)?.sync(IfExists::Error, false, false, &ctx)
+}
+
+#[test]
+fn sync_through_symlinked_dir() {
+    let env = with_nested();
+    let elsewhere = env.root().join("elsewhere");
+    fs::create_dir(&elsewhere).unwrap();
+    unix::fs::symlink(&elsewhere, env.home.join(".config")).unwrap();
+
+    sync(&env).unwrap();
+    // the link is made inside the dir that ~/.config points at
+    assert!(env.home.join(".config").is_symlink());
+    assert!(elsewhere.join("app").is_dir());
+    assert_eq!(
+        fs::read_link(elsewhere.join("app/rc")).unwrap(),
+        env.in_module("base", ".config/app/rc")
+    );
+    assert_eq!(
+        fs::read_to_string(env.home.join(".config/app/rc")).unwrap(),
+        "rc"
+    );
+}
+
+#[test]
+fn sync_refuses_broken_symlinked_dir() {
+    let env = with_nested();
+    let missing = env.root().join("missing");
+    unix::fs::symlink(&missing, env.home.join(".config")).unwrap();
+
+    let err = sync(&env).unwrap_err();
+    assert!(format!("{:?}", err).contains("broken"), "{:?}", err);
+    assert!(!missing.exists());
+}
+
+#[test]
+fn sync_refuses_symlinked_dir_into_repo() {
+    let env = with_nested();
+    let in_repo = env.repo.join(".stray");
+    fs::create_dir(&in_repo).unwrap();
+    unix::fs::symlink(&in_repo, env.home.join(".config")).unwrap();
+
+    let err = sync(&env).unwrap_err();
+    assert!(format!("{:?}", err).contains("repo"), "{:?}", err);
+    assert!(fs::read_dir(&in_repo).unwrap().next().is_none());
+}
+
+#[test]
+fn legacy_profile_dirs_move_only_when_asked() {
+    // made by new-profile of older versions
+    let env = Env::new(&["base", "work"]);
+    let conf = format!(
+        "{}[[profiles]]\nname = \"work\"\nmodules = []\n",
+        Env::conf(&["base"])
+    );
+    let mut ctx = env.ctx(&conf);
+
+    // like status
+    Profile::load(Some("test"), &ctx).unwrap();
+    assert!(env.repo.join("work").is_dir());
+
+    // like sync
+    ctx.migrate_profiles = true;
+    Profile::load(Some("test"), &ctx).unwrap();
+    assert!(!env.repo.join("work").exists());
+    assert!(env.repo.join(".profiles/work").is_dir());
+}
+
+#[test]
+fn state_of_paths_in_the_way() {
+    let env = with_nested();
+    let ctx = env.ctx(&Env::conf(&["base"]));
+    let profile = Profile::load(Some("test"), &ctx).unwrap();
+    let rc = env.home.join(".config/app/rc");
+    fs::create_dir_all(rc.parent().unwrap()).unwrap();
+    let e = profile
+        .modules
+        .get("base")
+        .unwrap()
+        .entry(common::s(&rc), &ctx)
+        .unwrap();
+
+    fs::write(&rc, "rc").unwrap();
+    assert_eq!(e.state().unwrap(), EntryState::Unsynced);
+    fs::write(&rc, "other").unwrap();
+    assert_eq!(e.state().unwrap(), EntryState::Conflict);
+    fs::remove_file(&rc).unwrap();
+    fs::create_dir(&rc).unwrap();
+    assert_eq!(e.state().unwrap(), EntryState::Conflict);
+    fs::remove_dir(&rc).unwrap();
+
+    // root can read anything
+    if !nix::unistd::geteuid().is_root() {
+        fs::write(&rc, "rc").unwrap();
+        fs::set_permissions(&rc, fs::Permissions::from_mode(0o000)).unwrap();
+        assert_eq!(e.state().unwrap(), EntryState::Conflict);
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs;
+
+use configma::entry::{generate_entry_set, walk_entries};
+
+/// peak resident memory of this process in kB (VmHWM)
+fn peak_rss() -> u64 {
+    let status = fs::read_to_string("/proc/self/status").unwrap();
+    let line = status.lines().find(|l| l.starts_with("VmHWM:")).unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+/// makes VmHWM start again from the current rss
+fn reset_peak_rss() {
+    fs::write("/proc/self/clear_refs", "5").unwrap();
+}
+
+#[test]
+#[ignore = "creates 100k files. run with --ignored --nocapture to see the peak rss"]
+fn walk_100k_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..100 {
+        for j in 0..10 {
+            let d = dir.path().join(format!("dir{}/sub{}", i, j));
+            fs::create_dir_all(&d).unwrap();
+            for k in 0..100 {
+                fs::write(d.join(format!("file{}", k)), "").unwrap();
+            }
+        }
+    }
+
+    reset_peak_rss();
+    let base = peak_rss();
+    let mut count = 0;
+    walk_entries(dir.path(), |_| {
+        count += 1;
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(count, 100_000);
+    println!(
+        "walk_entries: peak rss {} kB over {} kB",
+        peak_rss() - base,
+        base
+    );
+
+    reset_peak_rss();
+    let base = peak_rss();
+    let set = generate_entry_set(dir.path()).unwrap();
+    assert_eq!(set.len(), 100_000);
+    println!(
+        "generate_entry_set: peak rss {} kB over {} kB",
+        peak_rss() - base,
+        base
+    );
+}